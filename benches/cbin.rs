@@ -8,10 +8,11 @@ fn convert_pgn_with_file_io() {
     use std::fs::File;
     
     let file = File::open("games.pgn").unwrap();
-    let mut serializer = Serializer::new(std::io::sink());
+    let serializer = Serializer::new(std::io::sink());
     let mut converter = Converter::new(file, serializer);
     
     while converter.next_game().unwrap_or(false) {}
+    converter.finish().unwrap();
 }
 
 #[divan::bench]
@@ -26,10 +27,11 @@ fn convert_pgn_without_file_io(bencher: divan::Bencher) {
         })
         .bench_values(|pgn_data| {
             // Benchmark: just the conversion
-            let mut serializer = Serializer::new(std::io::sink());
+            let serializer = Serializer::new(std::io::sink());
             let mut converter = Converter::new(pgn_data.as_bytes(), serializer);
             
             while converter.next_game().unwrap_or(false) {}
+            converter.finish().unwrap();
         });
 }
 
@@ -0,0 +1,291 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::{Result, bail};
+use memmap2::Mmap;
+
+use crate::{
+    generated_chess::BlockIndexEntry,
+    reader::{ArchiveReader, ReadError},
+};
+
+/// Where new blocks go in an existing archive, found by `AppendPoint::find`. Pass it to `Serializer::append`.
+#[derive(Debug, Clone)]
+pub struct AppendPoint {
+    /// Byte offset right after the last complete block.
+    pub(crate) offset: u64,
+    pub(crate) dictionary: Option<Vec<u8>>,
+    /// Index entries of the blocks already in the archive.
+    pub(crate) index: Vec<BlockIndexEntry>,
+    /// Whether the archive ends with a block index that matches its blocks.
+    indexed: bool,
+    /// Number of bytes of an unfinished block at the end of the archive, which is dropped.
+    dropped: u64,
+}
+
+impl AppendPoint {
+    /// Checks an existing archive and finds where new blocks can be added to it: right after its last complete
+    /// block.
+    ///
+    /// The length prefixes of every block are walked, and the block index, if there is one, has to match them. The
+    /// last block's checksum is checked too, as it's the one an interrupted write would have left damaged. Without
+    /// an index, every block is decoded to count its games, so the index can be rebuilt. A block cut off at the end
+    /// of the file, as left behind by an interrupted conversion, is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive has no file header or was written before blocks had checksums and
+    /// encodings (`repair` upgrades those), if its header or index can't be read, or if it's damaged anywhere but
+    /// at the very end.
+    pub fn find(data: &[u8]) -> Result<Self> {
+        let reader = ArchiveReader::from_bytes(data)?;
+        let Some(header) = reader.header() else {
+            bail!(
+                "Archives written before file headers can't be appended to, upgrade it with repair first"
+            );
+        };
+        if !header.has_block_checksums() || !header.has_block_encodings() {
+            bail!(
+                "Archives written before blocks had checksums and encodings can't be appended to, upgrade it with \
+                 repair first"
+            );
+        }
+
+        let mut blocks = reader.blocks();
+        let mut walked = vec![];
+        let mut dropped = 0;
+        for block in blocks.by_ref() {
+            match block {
+                Ok(block) => walked.push(block),
+                Err(
+                    ReadError::TruncatedLength { offset }
+                    | ReadError::TruncatedBlock { offset, .. },
+                ) => {
+                    dropped = (data.len() - offset) as u64;
+                }
+                Err(err) => {
+                    bail!("Can't append to a damaged archive, repair it first: {err}");
+                }
+            }
+        }
+        if let Some(last) = walked.last() {
+            last.check_checksum()?;
+        }
+
+        let index = if let Some(index) = reader.index() {
+            let matches = index.len() == walked.len()
+                && index
+                    .iter()
+                    .zip(&walked)
+                    .all(|(entry, block)| entry.offset == block.offset() as u64);
+            if !matches {
+                bail!(
+                    "Can't append to an archive whose block index doesn't match its blocks, repair it first"
+                );
+            }
+            index.to_vec()
+        } else {
+            let mut index = Vec::with_capacity(walked.len());
+            let mut first_game = 0;
+            for block in &walked {
+                let game_count = u32::try_from(block.game_count()?)?;
+                index.push(BlockIndexEntry {
+                    offset: block.offset() as u64,
+                    first_game,
+                    game_count,
+                });
+                first_game += u64::from(game_count);
+            }
+            index
+        };
+
+        Ok(Self {
+            offset: blocks.offset() as u64,
+            dictionary: reader.dictionary().map(<[u8]>::to_vec),
+            index,
+            indexed: reader.index().is_some(),
+            dropped,
+        })
+    }
+
+    /// Gets the byte offset where new blocks start.
+    #[must_use]
+    pub const fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Gets the number of blocks already in the archive.
+    #[must_use]
+    pub const fn block_count(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Gets the number of games already in the archive.
+    #[must_use]
+    pub fn game_count(&self) -> u64 {
+        self.index
+            .last()
+            .map_or(0, |entry| entry.first_game + u64::from(entry.game_count))
+    }
+
+    /// Gets the number of bytes of an unfinished block at the end of the archive, which new blocks replace.
+    #[must_use]
+    pub const fn dropped_bytes(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// An archive file opened for appending, to be used as the writer of a `Serializer::append`.
+///
+/// Until `commit`, the new games stay hidden: the archive reads the same as before, only without its block index.
+/// If the writer is dropped without being committed, for example because the conversion failed, the archive is put
+/// back the way it was. If the process dies instead, the archive is left with its old games and no index, which
+/// appending again rebuilds.
+pub struct AppendWriter {
+    file: File,
+    /// Byte offset in the file where the appended data starts.
+    start: u64,
+    /// Number of bytes written to the file so far, counting the zeros in place of `first` but not `last`.
+    written: u64,
+    /// The first 4 bytes written, which are the length of the first new block (or the zero length ending the
+    /// blocks, if there are none). Zeros are written in their place until `commit`, so the blocks still end where
+    /// they used to.
+    first: Vec<u8>,
+    /// The last 4 bytes written so far, which end up being the magic bytes of the footer. Held back until
+    /// `commit`, so the new block index isn't used before then.
+    last: Vec<u8>,
+    /// Everything after the old last block, to put back if the writer isn't committed.
+    old_end: Vec<u8>,
+    committed: bool,
+}
+
+impl AppendWriter {
+    /// Opens the archive at the given path for appending, checking it with `AppendPoint::find`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be opened, read or written, or if the archive can't be appended to.
+    pub fn open(path: impl AsRef<Path>) -> Result<(Self, AppendPoint)> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let (point, old_end) = {
+            // Safety: the archive is only read through the map, which is dropped before the file is written.
+            let data = unsafe { Mmap::map(&file)? };
+            let point = AppendPoint::find(&data)?;
+            let old_end = if point.indexed {
+                data[usize::try_from(point.offset)?..].to_vec()
+            } else {
+                // Archives without an index may end without the zero length, or with an unfinished block.
+                vec![0; 4]
+            };
+            (point, old_end)
+        };
+
+        // Drop an unfinished block at the end, and hide the old index (by zeroing its magic bytes) before it's
+        // overwritten. The archive is then read by walking its blocks, which end at the zero length.
+        file.set_len(point.offset + old_end.len() as u64)?;
+        if point.indexed {
+            file.seek(SeekFrom::End(-4))?;
+            file.write_all(&[0; 4])?;
+        } else {
+            file.seek(SeekFrom::Start(point.offset))?;
+            file.write_all(&old_end)?;
+        }
+        file.sync_data()?;
+        file.seek(SeekFrom::Start(point.offset))?;
+
+        let writer = Self {
+            file,
+            start: point.offset,
+            written: 0,
+            first: vec![],
+            last: vec![],
+            old_end,
+            committed: false,
+        };
+        Ok((writer, point))
+    }
+
+    /// Makes the appended games part of the archive. Call it once the serializer has been finished.
+    ///
+    /// The archive is updated in three steps, each synced to disk before the next, so it reads correctly
+    /// whenever it's cut short: first everything but the held back bytes is written, then the length of the first
+    /// new block, so the new blocks follow the old ones, and finally the footer's magic bytes, so the new block
+    /// index (and with it the new game count) takes effect.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing or syncing the file fails. The archive is then put back the way it was when the
+    /// writer is dropped.
+    pub fn commit(&mut self) -> io::Result<()> {
+        if self.committed {
+            return Ok(());
+        }
+        // The file isn't always longer than what's been written: with no new blocks, the new end is where the old
+        // one was. So the magic bytes go right after the rest rather than at the end of the file, and anything
+        // past them is cut off.
+        let end = self.start + self.written;
+        self.file.set_len(end + self.last.len() as u64)?;
+        self.file.sync_data()?;
+        self.file.seek(SeekFrom::Start(self.start))?;
+        self.file.write_all(&self.first)?;
+        self.file.sync_data()?;
+        self.file.seek(SeekFrom::Start(end))?;
+        self.file.write_all(&self.last)?;
+        self.file.sync_all()?;
+        self.committed = true;
+        Ok(())
+    }
+
+    /// Writes data to the file, with zeros in place of the first 4 bytes.
+    fn put(&mut self, data: &[u8]) -> io::Result<()> {
+        let held = usize::try_from(4u64.saturating_sub(self.written))
+            .unwrap_or(4)
+            .min(data.len());
+        self.first.extend_from_slice(&data[..held]);
+        self.file.write_all(&[0; 4][..held])?;
+        self.file.write_all(&data[held..])?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    /// Puts the end of the archive back the way it was before appending.
+    fn roll_back(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.start))?;
+        self.file.write_all(&self.old_end)?;
+        self.file.set_len(self.start + self.old_end.len() as u64)?;
+        self.file.sync_all()
+    }
+}
+
+impl Write for AppendWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(split) = buf.len().checked_sub(4) {
+            let last = std::mem::replace(&mut self.last, buf[split..].to_vec());
+            self.put(&last)?;
+            self.put(&buf[..split])?;
+        } else {
+            self.last.extend_from_slice(buf);
+            let spill = self.last.len().saturating_sub(4);
+            let spilled: Vec<_> = self.last.drain(..spill).collect();
+            self.put(&spilled)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// Errors can't be reported from here, so they're ignored. The archive is then left with its old games and without an
+// index, as if the process had died.
+impl Drop for AppendWriter {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self.roll_back();
+        }
+    }
+}
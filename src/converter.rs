@@ -1,31 +1,252 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use std::{
+    collections::BTreeMap,
+    fmt,
     io::{Read, Write},
     ops::ControlFlow,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 
 use pgn_reader::Visitor;
 use planus::Offset;
+use shakmaty::Position;
 
 use crate::{
-    generated_chess::{CastleKind, Game, Move, Piece},
-    serializer::Serializer,
+    generated_chess::{CastleKind, CheckKind, Game, GameInfo, GameResult, Move, Piece},
+    serializer::{BlockStats, Serializer},
     utils::{self, role_to_piece, shakmaty_square_to_square},
 };
 
+/// Why a single game couldn't be converted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionErrorKind {
+    /// The movetext contains a null move (`--` or `Z0`), which the format can't represent.
+    NullMove,
+    /// The movetext contains a piece drop (eg. `N@f3`) from a Crazyhouse-style variant.
+    PieceDrop,
+    /// The `FEN` tag couldn't be parsed or doesn't describe a legal position.
+    InvalidFen(String),
+    /// Validation only: a move is illegal or ambiguous in the position it's played from.
+    IllegalMove {
+        /// One-based index of the half-move in the game.
+        ply: usize,
+        /// The move as it appeared in the PGN.
+        san: String,
+    },
+    /// Validation only: the game ends in checkmate or stalemate, but its result says otherwise.
+    ResultMismatch {
+        /// The result given by the PGN.
+        stored: GameResult,
+        /// The result implied by the final position.
+        expected: GameResult,
+    },
+}
+
+impl ConversionErrorKind {
+    /// A short, stable identifier for the kind of error, for grouping and machine-readable logs.
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::NullMove => "null_move",
+            Self::PieceDrop => "piece_drop",
+            Self::InvalidFen(_) => "invalid_fen",
+            Self::IllegalMove { .. } => "illegal_move",
+            Self::ResultMismatch { .. } => "result_mismatch",
+        }
+    }
+}
+
+impl fmt::Display for ConversionErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NullMove => write!(f, "null moves are not supported"),
+            Self::PieceDrop => write!(f, "piece drops are not supported"),
+            Self::InvalidFen(fen) => write!(f, "invalid FEN start position \"{fen}\""),
+            Self::IllegalMove { ply, san } => write!(f, "illegal move {san} at ply {ply}"),
+            Self::ResultMismatch { stored, expected } => write!(
+                f,
+                "result {stored:?} doesn't match the final position ({expected:?})"
+            ),
+        }
+    }
+}
+
+/// A game that couldn't be converted, along with where it came from in the PGN input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionError {
+    /// Zero-based index of the game in the PGN input.
+    pub game_index: usize,
+    /// Byte offset of the start of the game in the (decompressed) PGN input.
+    pub byte_offset: u64,
+    /// What went wrong.
+    pub kind: ConversionErrorKind,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "game {} at byte offset {}: {}",
+            self.game_index, self.byte_offset, self.kind
+        )
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Statistics about a conversion.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConversionStats {
+    /// Number of games read from the PGN input, including skipped games.
+    pub games_read: usize,
+    /// Number of games handed to the serializer.
+    pub games_written: usize,
+    /// Games that were skipped under `ErrorPolicy::Skip`, in the order they were read.
+    pub skipped_games: Vec<ConversionError>,
+    /// Statistics for every block written so far.
+    pub blocks: Vec<BlockStats>,
+    /// Total number of bytes written to the output.
+    pub bytes_written: u64,
+}
+
+impl ConversionStats {
+    /// Counts the skipped games by their `ConversionErrorKind::code`.
+    #[must_use]
+    pub fn skipped_by_reason(&self) -> BTreeMap<&'static str, usize> {
+        let mut counts = BTreeMap::new();
+        for skipped in &self.skipped_games {
+            *counts.entry(skipped.kind.code()).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+/// What the converter should do when it reaches a game it can't convert.
+///
+/// Errors writing to the output are never skipped, regardless of the policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop converting and return the `ConversionError` from `Converter::next_game`.
+    #[default]
+    Abort,
+    /// Drop the game and carry on with the next one. Skipped games are available from `Converter::skipped_games`.
+    Skip,
+}
+
+/// Why the visitor stopped early on a game.
+enum GameError {
+    Invalid(ConversionErrorKind),
+    Write(anyhow::Error),
+}
+
+/// Wraps a reader and keeps a running count of the bytes read through it, so that we can report the byte
+/// offset of a game even though `pgn_reader::Reader` owns (and buffers) the underlying reader.
+struct CountingReader<R: Read> {
+    inner: R,
+    bytes_read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes_read.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// The PGN tag pairs we keep for a game. Everything else in the header is dropped.
+#[derive(Default)]
+struct GameTags {
+    event: Option<String>,
+    site: Option<String>,
+    white: Option<String>,
+    black: Option<String>,
+    white_elo: u32,
+    black_elo: u32,
+    fen: Option<String>,
+    /// The game's result. Starts out as the `Result` tag, and is replaced by the result token at the end of
+    /// the movetext if there is one.
+    result: Option<GameResult>,
+    /// Whether the `SetUp` tag was explicitly set to "0", meaning any `FEN` tag should be ignored.
+    setup_disabled: bool,
+}
+
+impl GameTags {
+    /// Returns true if none of the tags we care about were present.
+    const fn is_empty(&self) -> bool {
+        self.event.is_none()
+            && self.site.is_none()
+            && self.white.is_none()
+            && self.black.is_none()
+            && self.white_elo == 0
+            && self.black_elo == 0
+    }
+}
+
+/// Decodes a tag value, treating empty values and the PGN `?` placeholder as missing.
+fn tag_value(value: &pgn_reader::RawTag<'_>) -> Option<String> {
+    let value = value.decode_utf8_lossy();
+    let value = value.trim();
+    if value.is_empty() || value == "?" {
+        None
+    } else {
+        Some(value.to_owned())
+    }
+}
+
+/// Parses an Elo tag. Unknown ratings (`?`, `-`, or garbage) are stored as 0.
+fn elo_value(value: &pgn_reader::RawTag<'_>) -> u32 {
+    tag_value(value).and_then(|v| v.parse().ok()).unwrap_or(0)
+}
+
 struct ConverterVisitor<W: Write> {
     serializer: Serializer<W>,
     current_moves: Vec<Offset<Move>>,
+    /// Whether to replay every game while converting it. See `Converter::set_validation`.
+    validate: bool,
+    /// The position reached so far in the current game. Only kept up to date when validating.
+    position: shakmaty::Chess,
 }
 
 impl<W: Write> Visitor for ConverterVisitor<W> {
-    type Tags = ();
+    type Tags = GameTags;
 
-    type Movetext = ();
+    type Movetext = GameTags;
 
-    type Output = ();
+    type Output = Result<(), GameError>;
 
     fn begin_tags(&mut self) -> std::ops::ControlFlow<Self::Output, Self::Tags> {
+        // A game that was cut short (eg. by returning `Break`) never reaches `end_game`,
+        // so make sure its moves don't end up in this one.
+        self.current_moves.clear();
+        ControlFlow::Continue(GameTags::default())
+    }
+
+    fn tag(
+        &mut self,
+        tags: &mut Self::Tags,
+        name: &[u8],
+        value: pgn_reader::RawTag<'_>,
+    ) -> ControlFlow<Self::Output> {
+        match name {
+            b"Event" => tags.event = tag_value(&value),
+            b"Site" => tags.site = tag_value(&value),
+            b"White" => tags.white = tag_value(&value),
+            b"Black" => tags.black = tag_value(&value),
+            b"WhiteElo" => tags.white_elo = elo_value(&value),
+            b"BlackElo" => tags.black_elo = elo_value(&value),
+            b"FEN" => tags.fen = tag_value(&value),
+            b"SetUp" => tags.setup_disabled = value.as_bytes() == b"0",
+            b"Result" => {
+                tags.result = shakmaty::Outcome::from_ascii(value.as_bytes())
+                    .ok()
+                    .map(utils::outcome_to_game_result);
+            }
+            _ => {}
+        }
         ControlFlow::Continue(())
     }
 
@@ -34,7 +255,15 @@ impl<W: Write> Visitor for ConverterVisitor<W> {
         _movetext: &mut Self::Movetext,
         san_plus: pgn_reader::SanPlus,
     ) -> ControlFlow<Self::Output> {
-        use pgn_reader::shakmaty::{CastlingSide, san::San};
+        use pgn_reader::shakmaty::{
+            CastlingSide,
+            san::{San, Suffix},
+        };
+
+        let check = san_plus.suffix.map(|suffix| match suffix {
+            Suffix::Check => CheckKind::Check,
+            Suffix::Checkmate => CheckKind::Checkmate,
+        });
 
         let made_move = match san_plus.san {
             San::Normal {
@@ -52,6 +281,7 @@ impl<W: Write> Visitor for ConverterVisitor<W> {
                 castle: None,
                 from_file: file.map(crate::utils::shakmaty_file_to_file),
                 from_rank: rank.map(crate::utils::shakmaty_rank_to_rank),
+                check,
             },
             San::Castle(side) => {
                 let castle_side = match side {
@@ -62,69 +292,220 @@ impl<W: Write> Visitor for ConverterVisitor<W> {
                 Move {
                     moved_piece: Piece::King,
                     castle: Some(castle_side),
+                    check,
                     ..Default::default()
                 }
             }
-            _ => panic!("Unsupported move type."),
+            San::Null => {
+                return ControlFlow::Break(Err(GameError::Invalid(ConversionErrorKind::NullMove)));
+            }
+            San::Put { .. } => {
+                return ControlFlow::Break(Err(GameError::Invalid(ConversionErrorKind::PieceDrop)));
+            }
         };
 
+        if self.validate {
+            let Ok(mv) = san_plus.san.to_move(&self.position) else {
+                return ControlFlow::Break(Err(GameError::Invalid(
+                    ConversionErrorKind::IllegalMove {
+                        ply: self.current_moves.len() + 1,
+                        san: san_plus.to_string(),
+                    },
+                )));
+            };
+            self.position.play_unchecked(mv);
+        }
+
         let offset = self.serializer.add_move(&made_move);
 
         self.current_moves.push(offset);
         ControlFlow::Continue(())
     }
 
-    fn outcome(
+    /// Only the mainline is stored, so variations are skipped entirely.
+    fn begin_variation(
         &mut self,
         _movetext: &mut Self::Movetext,
+    ) -> ControlFlow<Self::Output, pgn_reader::Skip> {
+        ControlFlow::Continue(pgn_reader::Skip(true))
+    }
+
+    fn outcome(
+        &mut self,
+        movetext: &mut Self::Movetext,
         outcome: shakmaty::Outcome,
     ) -> ControlFlow<Self::Output> {
-        let result = utils::outcome_to_game_result(outcome);
-        let res = Game::builder()
-            .result(result)
-            .start_position_as_null()
-            .moves(&self.current_moves);
-        self.serializer.add_game(&res).unwrap();
-        self.current_moves.clear();
+        movetext.result = Some(utils::outcome_to_game_result(outcome));
         ControlFlow::Continue(())
     }
 
     fn begin_movetext(
         &mut self,
-        _tags: Self::Tags,
+        mut tags: Self::Tags,
     ) -> std::ops::ControlFlow<Self::Output, Self::Movetext> {
-        ControlFlow::Continue(())
+        if tags.setup_disabled {
+            tags.fen = None;
+        }
+
+        // Store the start position in a normalized form, and skip the game entirely if it isn't a legal
+        // position: its moves could never be replayed.
+        if let Some(fen) = tags.fen.take() {
+            let Ok(position) = utils::start_position(Some(&fen)) else {
+                return ControlFlow::Break(Err(GameError::Invalid(
+                    ConversionErrorKind::InvalidFen(fen),
+                )));
+            };
+            tags.fen = Some(
+                shakmaty::fen::Fen::from_position(&position, shakmaty::EnPassantMode::Legal)
+                    .to_string(),
+            );
+            if self.validate {
+                self.position = position;
+            }
+        } else if self.validate {
+            self.position = shakmaty::Chess::default();
+        }
+
+        ControlFlow::Continue(tags)
+    }
+
+    /// Games are written here rather than in `outcome`, since not every game ends with a result token.
+    /// Games without one fall back to their `Result` tag, or `GameResult::Unknown` if that's missing too.
+    fn end_game(&mut self, movetext: Self::Movetext) -> Self::Output {
+        let result = movetext.result.unwrap_or(GameResult::Unknown);
+        if self.validate {
+            self.check_result(result)?;
+        }
+        let info = self.prepare_info(&movetext);
+        let start_position = movetext
+            .fen
+            .as_deref()
+            .map(|fen| self.serializer.add_string(fen));
+        let res = Game::builder()
+            .result(result)
+            .start_position(start_position)
+            .moves(&self.current_moves)
+            .info(info);
+        let added = self.serializer.add_game(&res);
+        self.current_moves.clear();
+        added.map(|_| ()).map_err(GameError::Write)
+    }
+}
+
+impl<W: Write> ConverterVisitor<W> {
+    /// Makes sure a game that ends in checkmate or stalemate has the matching result.
+    /// Any other final position is compatible with any result (resignation, time forfeit, agreed draw, ...), and an
+    /// unknown result (`*`) is compatible with any final position.
+    fn check_result(&self, stored: GameResult) -> Result<(), GameError> {
+        if stored == GameResult::Unknown
+            || (!self.position.is_checkmate() && !self.position.is_stalemate())
+        {
+            return Ok(());
+        }
+
+        let expected = utils::outcome_to_game_result(self.position.outcome());
+        if stored == expected {
+            Ok(())
+        } else {
+            Err(GameError::Invalid(ConversionErrorKind::ResultMismatch {
+                stored,
+                expected,
+            }))
+        }
     }
 
-    fn end_game(&mut self, _movetext: Self::Movetext) -> Self::Output {}
+    /// Serializes the `GameInfo` for a game's tags. Returns `None` if the game had no tags we keep,
+    /// so tagless games don't pay for an empty table.
+    fn prepare_info(&mut self, tags: &GameTags) -> Option<Offset<GameInfo>> {
+        if tags.is_empty() {
+            return None;
+        }
+
+        // Lichess (and others) put the game's URL in the [Site] tag.
+        let url = tags
+            .site
+            .as_deref()
+            .filter(|site| site.starts_with("https://") || site.starts_with("http://"));
+
+        let info = GameInfo::builder()
+            .event(tags.event.as_deref().map(|v| self.serializer.add_string(v)))
+            .site(tags.site.as_deref().map(|v| self.serializer.add_string(v)))
+            .url(url.map(|v| self.serializer.add_string(v)))
+            .white_player(tags.white.as_deref().map(|v| self.serializer.add_string(v)))
+            .black_player(tags.black.as_deref().map(|v| self.serializer.add_string(v)))
+            .white_elo(tags.white_elo)
+            .black_elo(tags.black_elo);
+
+        Some(self.serializer.add_game_info(&info))
+    }
 }
 
 /// Given a reader and a serializer, reads PGN from the serializer and converts it to
 /// a chess binary.
+///
+/// Call `finish` once all games are converted, so that errors writing the end of the archive are reported. A
+/// converter that's dropped without being finished tries to finish the archive anyway, but has to ignore any error.
 pub struct Converter<W: Write, R: Read> {
-    visitor: ConverterVisitor<W>,
-    pgn_parser: pgn_reader::Reader<R>,
-    game_count: usize,
+    // Only ever `None` after `finish` has taken the visitor, right before the converter is dropped.
+    visitor: Option<ConverterVisitor<W>>,
+    pgn_parser: pgn_reader::Reader<CountingReader<R>>,
+    bytes_read: Arc<AtomicU64>,
+    error_policy: ErrorPolicy,
+    stats: ConversionStats,
 }
 
 impl<W: Write, R: Read> Converter<W, R> {
     /// Creates a new converter instance from the given reader and serializer.
     ///
     /// Note that it must own both the reader and the serializer.
+    /// Games that can't be converted abort the conversion by default; see `set_error_policy`.
     pub fn new(reader: R, serializer: Serializer<W>) -> Self {
+        let bytes_read = Arc::new(AtomicU64::new(0));
         Self {
-            visitor: ConverterVisitor {
+            visitor: Some(ConverterVisitor {
                 serializer,
                 current_moves: vec![],
-            },
-            pgn_parser: pgn_reader::Reader::new(reader),
-            game_count: 0,
+                validate: false,
+                position: shakmaty::Chess::default(),
+            }),
+            pgn_parser: pgn_reader::Reader::new(CountingReader {
+                inner: reader,
+                bytes_read: Arc::clone(&bytes_read),
+            }),
+            bytes_read,
+            error_policy: ErrorPolicy::default(),
+            stats: ConversionStats::default(),
+        }
+    }
+
+    /// Enables or disables validation. When enabled, every game is replayed while it's converted: games with
+    /// illegal or ambiguous moves, or whose result contradicts a final checkmate or stalemate, are treated
+    /// like any other game that can't be converted (see `set_error_policy`).
+    ///
+    /// Validation is off by default, since replaying makes conversion noticeably slower.
+    pub const fn set_validation(&mut self, validate: bool) {
+        if let Some(visitor) = &mut self.visitor {
+            visitor.validate = validate;
         }
     }
 
+    /// Makes byte offsets count from the given offset, for a reader that starts partway into the PGN.
+    pub(crate) fn set_byte_offset(&self, offset: u64) {
+        self.bytes_read.fetch_add(offset, Ordering::Relaxed);
+    }
+
+    /// Sets what happens when a game can't be converted.
+    pub const fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.error_policy = error_policy;
+    }
+
     /// Returns true if there are more games to be read from the PGN file.
     /// Note that this requires some parsing from the pgn library, which is why
-    /// it has `&mut self` in there. Might throw if there are IO errors.
+    /// it has `&mut self` in there.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading from the underlying reader fails.
     pub fn has_more(&mut self) -> Result<bool> {
         self.pgn_parser
             .has_more()
@@ -134,29 +515,124 @@ impl<W: Write, R: Read> Converter<W, R> {
     /// Reads the next game the PGN file and converts it into the chess binary.
     ///
     /// Returns true if there was a game to read, false if there are no more games.
+    /// A game skipped under `ErrorPolicy::Skip` still counts as a game that was read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading or parsing the PGN fails, or if writing to the output fails.
+    /// Under `ErrorPolicy::Abort`, a game that can't be converted returns a `ConversionError`.
     pub fn next_game(&mut self) -> Result<bool> {
-        let return_val = self.pgn_parser.read_game(&mut self.visitor)?.is_some();
+        if !self.has_more()? {
+            return Ok(false);
+        }
 
-        self.game_count += 1;
+        // `has_more` skips to the start of the next game, so whatever is still buffered belongs to it.
+        let byte_offset =
+            self.bytes_read.load(Ordering::Relaxed) - self.pgn_parser.buffer().len() as u64;
+        let game_index = self.stats.games_read;
 
-        Ok(return_val)
+        let Some(visitor) = &mut self.visitor else {
+            bail!("Can't convert games after the converter has been finished");
+        };
+        let Some(result) = self.pgn_parser.read_game(visitor).with_context(|| {
+            format!("Failed to read game {game_index} at byte offset {byte_offset}")
+        })?
+        else {
+            return Ok(false);
+        };
+        self.stats.games_read += 1;
+
+        match result {
+            Ok(()) => {
+                self.stats.games_written += 1;
+                Ok(true)
+            }
+            Err(GameError::Write(err)) => Err(err),
+            Err(GameError::Invalid(kind)) => {
+                let error = ConversionError {
+                    game_index,
+                    byte_offset,
+                    kind,
+                };
+                match self.error_policy {
+                    ErrorPolicy::Abort => Err(error.into()),
+                    ErrorPolicy::Skip => {
+                        self.stats.skipped_games.push(error);
+                        Ok(true)
+                    }
+                }
+            }
+        }
     }
 
-    /// Flushes all the games converted so far to the output stream. Finishes the current block.
+    /// Flushes all the games converted so far to the output stream. Finishes the current block; more games can be
+    /// converted afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the output stream fails.
     pub fn flush(&mut self) -> Result<()> {
-        self.visitor.serializer.finish_current_block()
+        match &mut self.visitor {
+            Some(visitor) => visitor.serializer.finish_current_block(),
+            None => Ok(()),
+        }
     }
 
-    /// Gets the number of games that have been converted from the PGN file into
-    /// the chess binary.
+    /// Finishes the archive, writing the last block and the block index, then returns the output stream (so it can
+    /// be synced to disk or renamed, for example) along with the final conversion statistics.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the output stream fails.
+    pub fn finish(mut self) -> Result<(W, ConversionStats)> {
+        if let Some(visitor) = &mut self.visitor {
+            visitor.serializer.finish()?;
+        }
+        let stats = self.stats();
+        let Some(visitor) = self.visitor.take() else {
+            bail!("The converter has already been finished");
+        };
+        Ok((visitor.serializer.into_inner()?, stats))
+    }
+
+    /// Finishes the current block and returns the serializer, leaving the archive itself unfinished.
+    pub(crate) fn into_serializer(mut self) -> Result<Serializer<W>> {
+        let Some(mut visitor) = self.visitor.take() else {
+            bail!("The converter has already been finished");
+        };
+        visitor.serializer.finish_current_block()?;
+        Ok(visitor.serializer)
+    }
+
+    /// Gets the number of games that have been read from the PGN file, including any that were skipped.
     pub const fn game_count(&self) -> usize {
-        self.game_count
+        self.stats.games_read
+    }
+
+    /// Gets the games that were skipped under `ErrorPolicy::Skip`, in the order they were read.
+    pub fn skipped_games(&self) -> &[ConversionError] {
+        &self.stats.skipped_games
+    }
+
+    /// Returns a snapshot of the conversion statistics so far.
+    ///
+    /// Games that haven't been flushed yet are counted in `games_written` but not in `blocks` or `bytes_written`.
+    pub fn stats(&self) -> ConversionStats {
+        let mut stats = self.stats.clone();
+        if let Some(visitor) = &self.visitor {
+            stats.blocks = visitor.serializer.block_stats().to_vec();
+            stats.bytes_written = visitor.serializer.bytes_written();
+        }
+        stats
     }
 }
 
-// Blanket implementation so we don't forget to flush the last value.
+// Safety net for converters that are dropped without calling `finish`. Errors can't be reported from here, so
+// they're ignored; the archive is then left without a block index, which readers can cope with.
 impl<W: Write, R: Read> Drop for Converter<W, R> {
     fn drop(&mut self) {
-        self.flush().unwrap();
+        if let Some(visitor) = &mut self.visitor {
+            let _ = visitor.serializer.finish();
+        }
     }
 }
@@ -0,0 +1,178 @@
+use crate::reader::ReadError;
+
+/// Magic bytes at the start of every archive with a file header.
+pub const MAGIC: [u8; 4] = *b"CBIN";
+
+/// Size of the file header in bytes.
+pub const HEADER_LEN: usize = 12;
+
+/// Magic bytes at the very end of an archive with a block index.
+pub const INDEX_MAGIC: [u8; 4] = *b"CIDX";
+
+/// Size of the footer that points at the block index, in bytes.
+pub const FOOTER_LEN: usize = 12;
+
+/// Header flag: every block's length prefix is followed by a CRC32 checksum of the block data.
+pub const FLAG_BLOCK_CHECKSUMS: u32 = 1;
+
+/// Header flag: every block's checksum is followed by the block's encoding (see the `ENCODING_` constants).
+pub const FLAG_BLOCK_ENCODINGS: u32 = 2;
+
+/// Header flag: the header is followed by a zstd dictionary that blocks with `ENCODING_ZSTD_DICTIONARY` are
+/// compressed against.
+pub const FLAG_DICTIONARY: u32 = 4;
+
+/// Every flag this library understands. Archives with any other flag set are rejected.
+pub const KNOWN_FLAGS: u32 = FLAG_BLOCK_CHECKSUMS | FLAG_BLOCK_ENCODINGS | FLAG_DICTIONARY;
+
+/// Block encoding: the block data is the `Block` `FlatBuffer` itself.
+pub const ENCODING_NONE: u32 = 0;
+
+/// Block encoding: the block data is a zstd frame holding the `Block` `FlatBuffer`.
+pub const ENCODING_ZSTD: u32 = 1;
+
+/// Block encoding: the block data is a zstd frame holding the `Block` `FlatBuffer`, compressed against the
+/// archive's dictionary.
+pub const ENCODING_ZSTD_DICTIONARY: u32 = 2;
+
+/// Major format version written by this library. Readers reject archives with a different major version.
+pub const FORMAT_VERSION_MAJOR: u16 = 1;
+
+/// Minor format version written by this library. Minor versions only add things older readers can ignore.
+pub const FORMAT_VERSION_MINOR: u16 = 0;
+
+/// The fixed header at the start of an archive.
+///
+/// ```text
+/// | b"CBIN" | u16 major version | u16 minor version | u32 flags |
+/// ```
+///
+/// All integers are little-endian. With `FLAG_DICTIONARY` set, the header is followed by the zstd dictionary the
+/// blocks are compressed against:
+///
+/// ```text
+/// | u32 dictionary length | dictionary |
+/// ```
+///
+/// Then come the length-prefixed blocks. With
+/// `FLAG_BLOCK_CHECKSUMS` and `FLAG_BLOCK_ENCODINGS` set, each block is stored as:
+///
+/// ```text
+/// | u32 block length | u32 CRC32 of block data | u32 encoding | block data |
+/// ```
+///
+/// The length and checksum cover the block data as stored, so compressed blocks can be checked and copied without
+/// decompressing them.
+///
+/// A finished archive then ends with a block index:
+///
+/// ```text
+/// | u32 0 (end of blocks) | u32 index length | BlockIndex | u64 index offset | b"CIDX" |
+/// ```
+///
+/// The index offset points at the index's length prefix. Archives whose writer was interrupted have no index,
+/// and are read by walking the length prefixes instead.
+///
+/// Archives written before the header existed start straight away with the first block's length prefix. Those
+/// are still readable and have no header. (The magic bytes read as a block length of over 1.3 GB, which no legacy
+/// archive has.)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHeader {
+    /// Major format version. Changes whenever older readers can't read the archive anymore.
+    pub major: u16,
+    /// Minor format version.
+    pub minor: u16,
+    /// Feature flags, see the `FLAG_` constants.
+    pub flags: u32,
+}
+
+impl FileHeader {
+    /// The header for archives written by this version of the library.
+    pub const CURRENT: Self = Self {
+        major: FORMAT_VERSION_MAJOR,
+        minor: FORMAT_VERSION_MINOR,
+        flags: FLAG_BLOCK_CHECKSUMS | FLAG_BLOCK_ENCODINGS,
+    };
+
+    /// Whether blocks are stored with a checksum after their length prefix.
+    #[must_use]
+    pub const fn has_block_checksums(&self) -> bool {
+        self.flags & FLAG_BLOCK_CHECKSUMS != 0
+    }
+
+    /// Whether blocks are stored with an encoding after their checksum.
+    #[must_use]
+    pub const fn has_block_encodings(&self) -> bool {
+        self.flags & FLAG_BLOCK_ENCODINGS != 0
+    }
+
+    /// Whether the header is followed by a zstd dictionary.
+    #[must_use]
+    pub const fn has_dictionary(&self) -> bool {
+        self.flags & FLAG_DICTIONARY != 0
+    }
+
+    /// Reads the zstd dictionary following the header at the start of the given archive data, if the archive has
+    /// one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive ends before the end of the dictionary.
+    pub fn read_dictionary<'a>(&self, data: &'a [u8]) -> Result<Option<&'a [u8]>, ReadError> {
+        if !self.has_dictionary() {
+            return Ok(None);
+        }
+        let rest = data.get(HEADER_LEN..).unwrap_or_default();
+        let Some((length, rest)) = rest.split_first_chunk::<4>() else {
+            return Err(ReadError::TruncatedHeader);
+        };
+        let length = u32::from_le_bytes(*length) as usize;
+        rest.get(..length)
+            .map(Some)
+            .ok_or(ReadError::TruncatedHeader)
+    }
+
+    /// Encodes the header.
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&self.major.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.minor.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.flags.to_le_bytes());
+        bytes
+    }
+
+    /// Reads the header at the start of an archive. Returns `None` for archives without a header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the header is cut off, or if the archive uses a major version or flags this library
+    /// doesn't understand.
+    pub fn read(data: &[u8]) -> Result<Option<Self>, ReadError> {
+        if !data.starts_with(&MAGIC) {
+            return Ok(None);
+        }
+        let Some(bytes) = data.first_chunk::<HEADER_LEN>() else {
+            return Err(ReadError::TruncatedHeader);
+        };
+
+        let header = Self {
+            major: u16::from_le_bytes([bytes[4], bytes[5]]),
+            minor: u16::from_le_bytes([bytes[6], bytes[7]]),
+            flags: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+        };
+        if header.major != FORMAT_VERSION_MAJOR {
+            return Err(ReadError::UnsupportedVersion {
+                major: header.major,
+                minor: header.minor,
+            });
+        }
+        if header.flags & !KNOWN_FLAGS != 0 {
+            return Err(ReadError::UnsupportedFlags {
+                flags: header.flags & !KNOWN_FLAGS,
+            });
+        }
+        Ok(Some(header))
+    }
+}
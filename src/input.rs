@@ -0,0 +1,164 @@
+use std::{
+    io::{self, Cursor, Read},
+    path::Path,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread,
+};
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use liblzma::read::XzDecoder;
+
+/// Size of the pieces zip entries are handed over in.
+const ZIP_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Number of pieces of a zip entry that can be waiting to be read.
+const ZIP_CHUNKS_IN_FLIGHT: usize = 4;
+
+/// How PGN input is compressed, as told by its first few bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zstd,
+    Gzip,
+    Bzip2,
+    Xz,
+    /// A zip archive, whose PGN files are read one after the other.
+    Zip,
+}
+
+impl Compression {
+    /// Number of bytes `detect` needs to tell every format apart.
+    pub const MAGIC_LEN: usize = 6;
+
+    /// Tells the compression from the magic bytes at the start of the input. Anything unknown is taken to be plain
+    /// PGN.
+    #[must_use]
+    pub fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Self::Zstd
+        } else if magic.starts_with(&[0x1F, 0x8B]) {
+            Self::Gzip
+        } else if magic.starts_with(b"BZh") {
+            Self::Bzip2
+        } else if magic.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::Xz
+        } else if magic.starts_with(b"PK\x03\x04") {
+            Self::Zip
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Wraps PGN input in the right decoder for its compression, so it's decompressed as it's read.
+///
+/// The compression is detected from the magic bytes rather than a file name, so compressed input is recognized on
+/// standard input too. Concatenated gzip, bzip2, xz and zstd streams are read in full. For a zip archive, every PGN
+/// file in it (see `is_pgn_file`) is read in the order they're stored, with a blank line in between; other files are
+/// skipped.
+///
+/// # Errors
+///
+/// Returns an error if reading the magic bytes or setting up the decoder fails.
+pub fn decompress<R: Read + Send + 'static>(mut reader: R) -> io::Result<Box<dyn Read + Send>> {
+    let mut magic = Vec::with_capacity(Compression::MAGIC_LEN);
+    (&mut reader)
+        .take(Compression::MAGIC_LEN as u64)
+        .read_to_end(&mut magic)?;
+    let compression = Compression::detect(&magic);
+    // Put the magic bytes back in front of the rest of the input.
+    let reader = Cursor::new(magic).chain(reader);
+
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+        Compression::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+        Compression::Zip => Box::new(ZipEntries::new(reader)),
+    })
+}
+
+/// Whether a file is named like a PGN file: `*.pgn`, or `*.pgn.*` for a compressed one.
+#[must_use]
+pub fn is_pgn_file(path: &Path) -> bool {
+    let is_pgn = |path: &Path| {
+        path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("pgn"))
+    };
+    is_pgn(path) || path.file_stem().is_some_and(|stem| is_pgn(Path::new(stem)))
+}
+
+/// Reads the PGN files in a zip archive as one stream.
+///
+/// An entry of a zip stream borrows the reader it comes from, so the entries are read on a thread of their own and
+/// handed over a piece at a time.
+struct ZipEntries {
+    pieces: Receiver<io::Result<Vec<u8>>>,
+    piece: Cursor<Vec<u8>>,
+}
+
+impl ZipEntries {
+    fn new<R: Read + Send + 'static>(mut reader: R) -> Self {
+        let (sender, pieces) = mpsc::sync_channel(ZIP_CHUNKS_IN_FLIGHT);
+        thread::spawn(move || {
+            if let Err(err) = send_zip_entries(&mut reader, &sender) {
+                // Nobody's listening anymore if this fails, so there's no one left to tell.
+                let _ = sender.send(Err(err));
+            }
+        });
+        Self {
+            pieces,
+            piece: Cursor::default(),
+        }
+    }
+}
+
+impl Read for ZipEntries {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.piece.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            // The thread drops its end of the channel once every entry has been sent.
+            match self.pieces.recv() {
+                Ok(piece) => self.piece = Cursor::new(piece?),
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Sends every PGN file in a zip stream, stopping early if the receiving end is dropped.
+fn send_zip_entries<R: Read>(
+    reader: &mut R,
+    sender: &SyncSender<io::Result<Vec<u8>>>,
+) -> io::Result<()> {
+    let mut first = true;
+    while let Some(mut entry) = zip::read::read_zipfile_from_stream(reader)? {
+        if entry.is_dir() || !is_pgn_file(Path::new(entry.name()?.as_ref())) {
+            continue;
+        }
+        // Keep the first game of an entry apart from the last game of the previous one.
+        if !first && sender.send(Ok(b"\n\n".to_vec())).is_err() {
+            return Ok(());
+        }
+        first = false;
+
+        loop {
+            let mut piece = Vec::with_capacity(ZIP_CHUNK_SIZE);
+            (&mut entry)
+                .take(ZIP_CHUNK_SIZE as u64)
+                .read_to_end(&mut piece)?;
+            if piece.is_empty() {
+                break;
+            }
+            if sender.send(Ok(piece)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
@@ -4,7 +4,15 @@
 #![warn(clippy::cargo)]
 #![allow(clippy::multiple_crate_versions)]
 
+pub mod append;
 pub mod converter;
+pub mod header;
+pub mod input;
+pub mod merge;
+pub mod parallel;
+pub mod pgn_writer;
+pub mod reader;
+pub mod repair;
 pub mod serializer;
 pub mod utils;
 
@@ -4,34 +4,29 @@
 #![warn(clippy::cargo)]
 #![allow(clippy::multiple_crate_versions)]
 
-pub mod converter;
-pub mod serializer;
-pub mod utils;
-
 use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
+use memmap2::Mmap;
 
-#[allow(non_snake_case)]
-mod generated_chess {
-    #![allow(warnings)]
-    #![allow(clippy::all)]
-    include!(concat!(env!("OUT_DIR"), "/chess.rs"));
-    pub use chess::*;
-}
-
-use crate::converter::Converter;
-use crate::generated_chess::BlockRef;
-use crate::serializer::Serializer;
-use anyhow::Result;
+use anyhow::{Result, bail};
+use chessb::append::{AppendPoint, AppendWriter};
+use chessb::converter::{ConversionStats, Converter, ErrorPolicy};
+use chessb::generated_chess;
+use chessb::input::{decompress, is_pgn_file};
+use chessb::merge::merge;
+use chessb::parallel::ParallelConverter;
+use chessb::pgn_writer::PgnWriter;
+use chessb::reader::ArchiveReader;
+use chessb::repair::repair;
+use chessb::serializer::{Serializer, ShardLimit};
 use clap::{Parser, Subcommand};
-use memmap2::Mmap;
 use num_format::{Locale, ToFormattedString};
-use planus::ReadAsRoot;
 use rayon::prelude::*;
 use shakmaty::Position;
 use std::borrow::Cow;
 use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::Path;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
 
 #[derive(Parser)]
 #[command(name = "chessb")]
@@ -46,9 +41,60 @@ struct Cli {
 enum Commands {
     /// Convert PGN files to chess binary format
     Convert {
-        /// Input PGN file (supports .zst compression)
+        /// Input PGN files, directories (searched for PGN files and zip archives), glob patterns, or `-` for
+        /// standard input; zstd, gzip, bzip2, xz and zip input is detected and decompressed automatically
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        /// Output file, or `-` for standard output (defaults to input filename with .cbin extension, or to standard
+        /// output when reading standard input). When sharding, `{shard}` is replaced with the shard number
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Stop at the first game that can't be converted instead of skipping it
+        #[arg(long)]
+        abort_on_error: bool,
+        /// Replay every game while converting, skipping (or aborting on) illegal games and wrong results
+        #[arg(long)]
+        validate: bool,
+        /// Write a tab-separated log of skipped games (input, index, byte offset in the input, reason) to this file
+        #[arg(long)]
+        error_log: Option<String>,
+        /// Compress every block with zstd at this level (1-22); blocks are stored uncompressed by default
+        #[arg(long)]
+        compress_level: Option<i32>,
+        /// Train a zstd dictionary of up to this many bytes on the first block and compress every block against it
+        #[arg(long, requires = "compress_level")]
+        dictionary_size: Option<usize>,
+        /// Finish each block after this many games (defaults to 500,000)
+        #[arg(long, conflicts_with = "block_bytes")]
+        block_games: Option<usize>,
+        /// Finish each block once it reaches this many bytes before compression, instead of after a number of games
+        #[arg(long)]
+        block_bytes: Option<usize>,
+        /// Convert on this many threads (0 for one per core); conversion is single-threaded by default
+        #[arg(long)]
+        threads: Option<usize>,
+        /// With --threads, convert this many bytes of PGN at a time on each thread (defaults to 16 MiB). Every chunk
+        /// ends a block, so this also caps how big blocks get
+        #[arg(long, requires = "threads")]
+        chunk_size: Option<usize>,
+        /// Start a new output file after this many games (with --threads, files end at the first block boundary after
+        /// the limit instead)
+        #[arg(long, conflicts_with = "shard_bytes")]
+        shard_games: Option<u64>,
+        /// Start a new output file once the current one reaches this many bytes (files end between blocks, so they
+        /// overshoot by up to a block)
+        #[arg(long)]
+        shard_bytes: Option<u64>,
+        /// Add the games to the end of an existing output file instead of replacing it; the file is left as it was
+        /// if the conversion fails
+        #[arg(long, conflicts_with_all = ["shard_games", "shard_bytes", "dictionary_size"])]
+        append: bool,
+    },
+    /// Export chess binary files back to PGN
+    Export {
+        /// Input chess binary file (.cbin)
         input: String,
-        /// Output file (defaults to input filename with .cbin extension)
+        /// Output PGN file (defaults to input filename with .pgn extension)
         #[arg(short, long)]
         output: Option<String>,
     },
@@ -57,56 +103,705 @@ enum Commands {
         /// Input chess binary file (.cbin)
         input: String,
     },
+    /// Check every block of a chess binary file for truncation and corruption
+    Verify {
+        /// Input chess binary file (.cbin)
+        input: String,
+    },
+    /// Salvage the intact blocks of a truncated or corrupted chess binary file into a new file
+    Repair {
+        /// Damaged chess binary file (.cbin)
+        input: String,
+        /// Output file (defaults to input filename with .repaired.cbin extension)
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Compress every block with zstd at this level (1-22); blocks are stored uncompressed by default
+        #[arg(long)]
+        compress_level: Option<i32>,
+    },
+    /// Merge several chess binary files into one, re-packing the games of partly filled blocks into full blocks
+    Merge {
+        /// Input chess binary files (.cbin), merged in the order given
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        /// Output file
+        #[arg(short, long)]
+        output: String,
+        /// Compress every block with zstd at this level (1-22); blocks are stored uncompressed by default
+        #[arg(long)]
+        compress_level: Option<i32>,
+        /// Train a zstd dictionary of up to this many bytes on the first block and compress every block against it
+        #[arg(long, requires = "compress_level")]
+        dictionary_size: Option<usize>,
+        /// Finish each block after this many games (defaults to 500,000); blocks of exactly this many games are
+        /// copied over whole
+        #[arg(long, conflicts_with = "block_bytes")]
+        block_games: Option<usize>,
+        /// Finish each block once it reaches this many bytes before compression, instead of after a number of games
+        #[arg(long)]
+        block_bytes: Option<usize>,
+    },
+    /// Print a single game from a chess binary file as PGN
+    Show {
+        /// Input chess binary file (.cbin)
+        input: String,
+        /// Index of the game in the archive, counting from 0
+        game: u64,
+    },
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Convert { input, output } => {
-            let output_file = output.unwrap_or_else(|| generate_default_output_filename(&input));
-            convert_file(&input, &output_file)
+        Commands::Convert {
+            inputs,
+            output,
+            abort_on_error,
+            validate,
+            error_log,
+            compress_level,
+            dictionary_size,
+            block_games,
+            block_bytes,
+            threads,
+            chunk_size,
+            shard_games,
+            shard_bytes,
+            append,
+        } => {
+            let shard_limit = shard_games
+                .map(ShardLimit::Games)
+                .or_else(|| shard_bytes.map(ShardLimit::Bytes));
+            let output_file = match output {
+                Some(output) => output,
+                None => default_convert_output(&inputs, shard_limit.is_some())?,
+            };
+            let error_policy = if abort_on_error {
+                ErrorPolicy::Abort
+            } else {
+                ErrorPolicy::Skip
+            };
+            convert_file(
+                &expand_inputs(&inputs)?,
+                &output_file,
+                error_policy,
+                validate,
+                error_log.as_deref(),
+                &OutputSettings {
+                    compress_level,
+                    dictionary_size,
+                    block_games,
+                    block_bytes,
+                    shard_limit,
+                    append,
+                },
+                threads.map(|threads| ParallelSettings {
+                    threads,
+                    chunk_size,
+                }),
+            )
+        }
+        Commands::Export { input, output } => {
+            let output_file = output.unwrap_or_else(|| {
+                Path::new(&input)
+                    .with_extension("pgn")
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            export_file(&input, &output_file)
         }
         Commands::Read { input } => read_file(&input),
+        Commands::Merge {
+            inputs,
+            output,
+            compress_level,
+            dictionary_size,
+            block_games,
+            block_bytes,
+        } => merge_files(
+            &inputs,
+            &output,
+            &OutputSettings {
+                compress_level,
+                dictionary_size,
+                block_games,
+                block_bytes,
+                shard_limit: None,
+                append: false,
+            },
+        ),
+        Commands::Show { input, game } => show_game(&input, game),
+        Commands::Verify { input } => verify_file(&input),
+        Commands::Repair {
+            input,
+            output,
+            compress_level,
+        } => {
+            let output_file = output.unwrap_or_else(|| {
+                Path::new(&input)
+                    .with_extension("repaired.cbin")
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            repair_file(&input, &output_file, compress_level)
+        }
     }
 }
 
-fn convert_file(input_file: &str, output_file: &str) -> Result<()> {
-    println!("Reading from {input_file}");
-    println!("Writing to {output_file}");
+/// How `convert` (and `merge`) sizes and compresses the blocks it writes, splits its output into shards, or adds
+/// them to an existing archive.
+struct OutputSettings {
+    compress_level: Option<i32>,
+    dictionary_size: Option<usize>,
+    block_games: Option<usize>,
+    block_bytes: Option<usize>,
+    shard_limit: Option<ShardLimit>,
+    append: bool,
+}
 
-    let file = File::open(input_file)?;
-    let len = file.metadata()?.len();
-    let buf_reader = BufReader::new(file);
-    let progress_wrapped = ProgressBar::new(len)
-        .wrap_read(buf_reader)
-        .with_message("Reading and converting...")
-        .with_finish(ProgressFinish::WithMessage(Cow::from(
-            "Finished converting file!",
-        )))
-        .with_style(ProgressStyle::with_template(
-            "{msg} {percent_precise}% {bar:40.cyan/blue} [{decimal_bytes_per_sec}, {eta} left]",
-        )?);
+impl OutputSettings {
+    fn apply<W: Write>(&self, serializer: &mut Serializer<W>) {
+        serializer.set_compression_level(self.compress_level);
+        serializer.set_dictionary_size(self.dictionary_size);
+        if let Some(block_games) = self.block_games {
+            serializer.set_max_games_per_block(block_games);
+        }
+        if let Some(block_bytes) = self.block_bytes {
+            // A byte limit replaces the game limit rather than adding to it.
+            serializer.set_max_games_per_block(usize::MAX);
+            serializer.set_max_block_bytes(block_bytes);
+        }
+    }
+}
+
+/// Tells where `convert` writes the archive.
+fn describe_output(
+    log: &mut dyn Write,
+    output_file: &str,
+    sharded: bool,
+    append_point: Option<&AppendPoint>,
+) -> Result<()> {
+    if sharded {
+        writeln!(log, "Writing to shards named {output_file}")?;
+    } else if let Some(point) = append_point {
+        writeln!(
+            log,
+            "Appending to {output_file}, which has {} games in {} blocks",
+            point.game_count().to_formatted_string(&Locale::en),
+            point.block_count().to_formatted_string(&Locale::en)
+        )?;
+        if point.dropped_bytes() > 0 {
+            writeln!(
+                log,
+                "Dropping an unfinished block of {} bytes at the end",
+                point.dropped_bytes().to_formatted_string(&Locale::en)
+            )?;
+        }
+    } else {
+        writeln!(
+            log,
+            "Writing to {}",
+            display_path(Path::new(output_file), "standard output")
+        )?;
+    }
+    Ok(())
+}
 
-    let reader: Box<dyn Read> = if Path::new(input_file)
-        .extension()
-        .is_some_and(|ext| ext.eq_ignore_ascii_case("zst"))
+/// How `convert` splits the work across threads, when it's asked to.
+struct ParallelSettings {
+    threads: usize,
+    chunk_size: Option<usize>,
+}
+
+fn convert_file(
+    inputs: &[PathBuf],
+    output_file: &str,
+    error_policy: ErrorPolicy,
+    validate: bool,
+    error_log: Option<&str>,
+    output_settings: &OutputSettings,
+    parallel: Option<ParallelSettings>,
+) -> Result<()> {
+    let shards = output_settings
+        .shard_limit
+        .map(|_| ShardNames::new(output_file));
+    if let Some(shards) = &shards {
+        shards.check()?;
+    }
+    let (output, append_point) = open_output(output_file, shards.as_ref(), output_settings.append)?;
+    // The archive itself may be going to standard output, so status messages go to standard error then.
+    let mut log: Box<dyn Write> = if output.is_stdout() {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    };
+    match inputs {
+        [input] => writeln!(
+            log,
+            "Reading from {}",
+            display_path(input, "standard input")
+        )?,
+        _ => writeln!(log, "Reading from {} inputs", inputs.len())?,
+    }
+    describe_output(
+        &mut log,
+        output_file,
+        shards.is_some(),
+        append_point.as_ref(),
+    )?;
+    let block_limit =
+        output_settings.block_games.is_some() || output_settings.block_bytes.is_some();
+    if block_limit
+        && parallel
+            .as_ref()
+            .is_some_and(|parallel| parallel.chunk_size.is_none())
     {
-        Box::new(zstd::Decoder::new(progress_wrapped)?)
+        writeln!(
+            log,
+            "Warning: with --threads, every 16 MiB chunk of PGN ends a block, so blocks may end before \
+             --block-games or --block-bytes is reached; use --chunk-size to convert bigger chunks"
+        )?;
+    }
+
+    // Without a known size, such as when reading a pipe, the progress bar just counts bytes.
+    let progress_bar = match total_input_len(inputs)? {
+        Some(len) => ProgressBar::new(len).with_style(ProgressStyle::with_template(
+            "{msg} {percent_precise}% {bar:40.cyan/blue} [{decimal_bytes_per_sec}, {eta} left]",
+        )?),
+        None => ProgressBar::no_length().with_style(ProgressStyle::with_template(
+            "{msg} {decimal_bytes} [{decimal_bytes_per_sec}]",
+        )?),
+    }
+    .with_message("Reading and converting...")
+    .with_finish(ProgressFinish::WithMessage(Cow::from(
+        "Finished converting file!",
+    )));
+    let reader = InputChain::new(inputs.to_vec(), progress_bar);
+    let input_starts = Arc::clone(&reader.starts);
+
+    let mut serializer = match append_point {
+        Some(point) => Serializer::append(output, point),
+        None => Serializer::new(output),
+    };
+    output_settings.apply(&mut serializer);
+    if let (Some(limit), Some(shards)) = (output_settings.shard_limit, &shards) {
+        let shards = shards.clone();
+        serializer.set_shards(limit, move |previous: &mut Output| {
+            previous.sync()?;
+            Output::create(&shards.next())
+        });
+    }
+    let (mut output, stats) = if let Some(parallel) = parallel {
+        let mut converter = ParallelConverter::new(reader, serializer);
+        converter.set_error_policy(error_policy);
+        converter.set_validation(validate);
+        converter.set_threads(parallel.threads);
+        if let Some(chunk_size) = parallel.chunk_size {
+            converter.set_chunk_size(chunk_size);
+        }
+        converter.convert()?
     } else {
-        Box::new(progress_wrapped)
+        let mut converter = Converter::new(reader, serializer);
+        converter.set_error_policy(error_policy);
+        converter.set_validation(validate);
+        while converter.next_game()? {}
+        converter.finish()?
     };
+    output.sync()?;
+
+    print_conversion_summary(&mut log, &stats)?;
+    if let Some(shards) = &shards {
+        let written = shards.written();
+        writeln!(log, "Shards written: {}", written.len())?;
+        for path in written {
+            writeln!(log, "  {path}")?;
+        }
+    }
+
+    if let Some(error_log) = error_log {
+        write_error_log(
+            error_log,
+            &stats,
+            &input_starts.lock().unwrap_or_else(PoisonError::into_inner),
+        )?;
+        writeln!(log, "Wrote error log to {error_log}")?;
+    }
+
+    Ok(())
+}
+
+/// Opens where `convert` writes to: the first shard, the end of an existing archive when appending (along with where
+/// in it the new blocks go), or just the output file.
+fn open_output(
+    output_file: &str,
+    shards: Option<&ShardNames>,
+    append: bool,
+) -> Result<(Output, Option<AppendPoint>)> {
+    if append {
+        if output_file == STDIO_PATH {
+            bail!("Can't append to standard output");
+        }
+        let (writer, point) = AppendWriter::open(output_file)?;
+        return Ok((Output::Append(writer), Some(point)));
+    }
+    let path = shards.map_or_else(|| output_file.to_owned(), ShardNames::first);
+    Ok((Output::create(&path)?, None))
+}
+
+/// The path that stands for standard input or standard output.
+const STDIO_PATH: &str = "-";
+
+/// The part of a shard's file name that's replaced with the shard number.
+const SHARD_PLACEHOLDER: &str = "{shard}";
+
+fn display_path<'a>(path: &'a Path, stdio: &'a str) -> Cow<'a, str> {
+    if path == Path::new(STDIO_PATH) {
+        Cow::from(stdio)
+    } else {
+        path.to_string_lossy()
+    }
+}
+
+/// Picks the output file of `convert` when none is given.
+fn default_convert_output(inputs: &[String], sharding: bool) -> Result<String> {
+    match inputs {
+        [input] if input == STDIO_PATH && !sharding => Ok(STDIO_PATH.to_owned()),
+        [input] if input != STDIO_PATH => {
+            let suffix = if sharding {
+                format!("-{SHARD_PLACEHOLDER}")
+            } else {
+                String::new()
+            };
+            Ok(generate_default_output_filename(input, &suffix))
+        }
+        _ => bail!("Pick an output file with --output when converting these inputs"),
+    }
+}
+
+/// Turns the inputs given to `convert` into a list of files: directories are searched for PGN files, and
+/// anything that isn't a file or directory is taken as a glob pattern, for shells that don't expand them.
+fn expand_inputs(patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut inputs = vec![];
+    for pattern in patterns {
+        let path = Path::new(pattern);
+        if pattern == STDIO_PATH || path.is_file() {
+            inputs.push(path.to_owned());
+        } else if path.is_dir() {
+            find_pgn_files(path, &mut inputs)?;
+        } else {
+            let found = inputs.len();
+            for path in glob::glob(pattern)? {
+                let path = path?;
+                if path.is_dir() {
+                    find_pgn_files(&path, &mut inputs)?;
+                } else {
+                    inputs.push(path);
+                }
+            }
+            if inputs.len() == found {
+                bail!("No input files found for {pattern}");
+            }
+        }
+    }
+    Ok(inputs)
+}
+
+/// Adds the PGN files in a directory and its subdirectories, in order of their paths. PGN files are the ones
+/// named `*.pgn` or `*.pgn.*` (see `is_pgn_file`), along with zip archives, which usually hold PGN files.
+fn find_pgn_files(dir: &Path, found: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_pgn_files(&path, found)?;
+        } else if is_pgn_file(&path)
+            || path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+        {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Adds up the sizes of the inputs, if they're all files.
+fn total_input_len(inputs: &[PathBuf]) -> Result<Option<u64>> {
+    let mut total = 0;
+    for input in inputs {
+        if input == Path::new(STDIO_PATH) {
+            return Ok(None);
+        }
+        total += std::fs::metadata(input)?.len();
+    }
+    Ok(Some(total))
+}
+
+/// Where one of the inputs of an `InputChain` starts in the combined stream.
+struct InputStart {
+    path: PathBuf,
+    offset: u64,
+}
+
+/// Reads the inputs of `convert` one after the other as a single PGN stream. Each input is decompressed on its own,
+/// and opened only once the previous one has been read, so there's never more than one input open at a time.
+struct InputChain {
+    inputs: std::vec::IntoIter<PathBuf>,
+    current: Option<(PathBuf, Box<dyn Read + Send>)>,
+    progress_bar: ProgressBar,
+    /// Where every input opened so far starts, so byte offsets can be traced back to an input.
+    starts: Arc<Mutex<Vec<InputStart>>>,
+    /// Number of bytes read so far.
+    offset: u64,
+    /// What's left of the blank line put between two inputs, so the first game of an input isn't taken as part of
+    /// the last game of the previous one.
+    separator: &'static [u8],
+}
+
+impl InputChain {
+    fn new(inputs: Vec<PathBuf>, progress_bar: ProgressBar) -> Self {
+        Self {
+            inputs: inputs.into_iter(),
+            current: None,
+            progress_bar,
+            starts: Arc::default(),
+            offset: 0,
+            separator: &[],
+        }
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let input: Box<dyn Read + Send> = if path == Path::new(STDIO_PATH) {
+            Box::new(io::stdin())
+        } else {
+            Box::new(File::open(path)?)
+        };
+        decompress(self.progress_bar.wrap_read(BufReader::new(input)))
+    }
+}
+
+impl Read for InputChain {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if !self.separator.is_empty() {
+                let len = self.separator.len().min(buf.len());
+                buf[..len].copy_from_slice(&self.separator[..len]);
+                self.separator = &self.separator[len..];
+                self.offset += len as u64;
+                return Ok(len);
+            }
+
+            if let Some((path, input)) = &mut self.current {
+                let read = input.read(buf).map_err(|err| with_path(path, &err))?;
+                if read > 0 {
+                    self.offset += read as u64;
+                    return Ok(read);
+                }
+                self.current = None;
+            }
+
+            let Some(path) = self.inputs.next() else {
+                return Ok(0);
+            };
+            let input = self.open(&path).map_err(|err| with_path(&path, &err))?;
+            let mut starts = self.starts.lock().unwrap_or_else(PoisonError::into_inner);
+            if !starts.is_empty() {
+                self.separator = b"\n\n";
+            }
+            starts.push(InputStart {
+                path: path.clone(),
+                offset: self.offset + self.separator.len() as u64,
+            });
+            drop(starts);
+            self.current = Some((path, input));
+        }
+    }
+}
+
+/// Adds the input an error came from to its message, since there can be hundreds of them.
+fn with_path(path: &Path, err: &io::Error) -> io::Error {
+    io::Error::new(
+        err.kind(),
+        format!("{}: {err}", display_path(path, "standard input")),
+    )
+}
+
+/// File names of the shards `convert` writes, made from a template with a `{shard}` placeholder.
+#[derive(Clone)]
+struct ShardNames {
+    template: String,
+    /// Every shard started so far, shared between the clone that names new shards and the one that reports them.
+    written: Arc<Mutex<Vec<String>>>,
+}
+
+impl ShardNames {
+    fn new(template: &str) -> Self {
+        Self {
+            template: template.to_owned(),
+            written: Arc::default(),
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.template == STDIO_PATH {
+            bail!("Sharded output can't be written to standard output");
+        }
+        if !self.template.contains(SHARD_PLACEHOLDER) {
+            bail!(
+                "The output file name needs a {SHARD_PLACEHOLDER} placeholder for the shard number when sharding"
+            );
+        }
+        Ok(())
+    }
+
+    fn first(&self) -> String {
+        self.next()
+    }
+
+    /// Names the next shard. Numbers are padded to four digits, so that shards sort in order.
+    fn next(&self) -> String {
+        let mut written = self.written.lock().unwrap_or_else(PoisonError::into_inner);
+        let path = self
+            .template
+            .replace(SHARD_PLACEHOLDER, &format!("{:04}", written.len()));
+        written.push(path.clone());
+        path
+    }
+
+    fn written(&self) -> Vec<String> {
+        self.written
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+/// Where `convert` writes the archive: a file, standard output for `-`, or the end of an existing archive.
+enum Output {
+    File(File),
+    Stdout(BufWriter<io::Stdout>),
+    Append(AppendWriter),
+}
+
+impl Output {
+    fn create(path: &str) -> Result<Self> {
+        if path == STDIO_PATH {
+            Ok(Self::Stdout(BufWriter::new(io::stdout())))
+        } else {
+            Ok(Self::File(File::create(path)?))
+        }
+    }
 
-    let out_file = File::create(output_file)?;
-    let serializer = Serializer::new(out_file);
-    let mut converter = Converter::new(reader, serializer);
+    const fn is_stdout(&self) -> bool {
+        matches!(self, Self::Stdout(_))
+    }
+
+    /// Makes sure the archive has made it to disk, or into the pipe. Appended games only become part of the archive
+    /// here.
+    fn sync(&mut self) -> Result<()> {
+        match self {
+            Self::File(file) => file.sync_all()?,
+            Self::Stdout(stdout) => stdout.flush()?,
+            Self::Append(writer) => writer.commit()?,
+        }
+        Ok(())
+    }
+}
 
-    while converter.next_game().is_ok_and(|x| x) {}
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.write(buf),
+            Self::Stdout(stdout) => stdout.write(buf),
+            Self::Append(writer) => writer.write(buf),
+        }
+    }
 
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::File(file) => file.flush(),
+            Self::Stdout(stdout) => stdout.flush(),
+            Self::Append(writer) => writer.flush(),
+        }
+    }
+}
+
+fn print_conversion_summary(log: &mut dyn Write, stats: &ConversionStats) -> io::Result<()> {
+    writeln!(
+        log,
+        "Games read: {}",
+        stats.games_read.to_formatted_string(&Locale::en)
+    )?;
+    writeln!(
+        log,
+        "Games written: {}",
+        stats.games_written.to_formatted_string(&Locale::en)
+    )?;
+    writeln!(
+        log,
+        "Games skipped: {}",
+        stats.skipped_games.len().to_formatted_string(&Locale::en)
+    )?;
+    for (reason, count) in stats.skipped_by_reason() {
+        writeln!(
+            log,
+            "  {reason}: {}",
+            count.to_formatted_string(&Locale::en)
+        )?;
+    }
+
+    writeln!(log, "Blocks written: {}", stats.blocks.len())?;
+    for (index, block) in stats.blocks.iter().enumerate() {
+        writeln!(
+            log,
+            "  Block {index}: {} games, {} unique moves, {} bytes",
+            block.games.to_formatted_string(&Locale::en),
+            block.unique_moves.to_formatted_string(&Locale::en),
+            block.bytes.to_formatted_string(&Locale::en)
+        )?;
+    }
+    writeln!(
+        log,
+        "Bytes written: {}",
+        stats.bytes_written.to_formatted_string(&Locale::en)
+    )?;
+    Ok(())
+}
+
+/// Writes one line per skipped game, so bad games can be found again in the original PGN. Byte offsets count from
+/// the start of the (decompressed) input the game is in.
+fn write_error_log(path: &str, stats: &ConversionStats, inputs: &[InputStart]) -> Result<()> {
+    let mut log = BufWriter::new(File::create(path)?);
+    writeln!(log, "input\tgame_index\tbyte_offset\treason\tmessage")?;
+    for skipped in &stats.skipped_games {
+        let index = inputs.partition_point(|input| input.offset <= skipped.byte_offset);
+        let (input, byte_offset) = index.checked_sub(1).map_or_else(
+            || (Cow::from(""), skipped.byte_offset),
+            |index| {
+                (
+                    display_path(&inputs[index].path, STDIO_PATH),
+                    skipped.byte_offset - inputs[index].offset,
+                )
+            },
+        );
+        writeln!(
+            log,
+            "{}\t{}\t{}\t{}\t{}",
+            input,
+            skipped.game_index,
+            byte_offset,
+            skipped.kind.code(),
+            skipped.kind
+        )?;
+    }
+    log.flush()?;
     Ok(())
 }
 
-fn generate_default_output_filename(input_file: &str) -> String {
+fn generate_default_output_filename(input_file: &str, suffix: &str) -> String {
     let path = Path::new(input_file);
 
     let filename = path
@@ -114,11 +809,12 @@ fn generate_default_output_filename(input_file: &str) -> String {
         .and_then(|s| s.to_str())
         .unwrap_or("output");
 
-    // If input was .zst, strip one more extension (e.g., "file.pgn.zst" -> "file")
-    let stem = if path
-        .extension()
-        .is_some_and(|ext| ext.eq_ignore_ascii_case("zst"))
-    {
+    // If input was compressed, strip one more extension (e.g., "file.pgn.zst" -> "file")
+    let stem = if path.extension().is_some_and(|ext| {
+        ["zst", "gz", "bz2", "xz"]
+            .iter()
+            .any(|compressed| ext.eq_ignore_ascii_case(compressed))
+    }) {
         Path::new(filename)
             .file_stem()
             .and_then(|s| s.to_str())
@@ -127,72 +823,202 @@ fn generate_default_output_filename(input_file: &str) -> String {
         filename
     };
 
-    format!("{stem}.cbin")
+    format!("{stem}{suffix}.cbin")
 }
 
-struct BlockIterator<'a> {
-    data: &'a [u8],
-    offset: usize,
-}
+fn export_file(input_file: &str, output_file: &str) -> Result<()> {
+    println!("Reading from {input_file}");
+    println!("Writing to {output_file}");
+
+    let reader = ArchiveReader::open(input_file)?;
 
-impl<'a> BlockIterator<'a> {
-    const fn new(data: &'a [u8]) -> Self {
-        Self { data, offset: 0 }
+    let progress_bar = ProgressBar::new((reader.data().len() - reader.blocks_offset()) as u64);
+    progress_bar.set_style(ProgressStyle::with_template(
+        "{msg} {percent}% {bar:40.cyan/blue} [{decimal_bytes_per_sec}, {eta} left]",
+    )?);
+    progress_bar.set_message("Exporting games");
+
+    let mut writer = PgnWriter::new(BufWriter::new(File::create(output_file)?));
+    for block in reader.blocks() {
+        let block = block?;
+        writer.write_block(block.decode()?.data())?;
+        progress_bar.inc(block.stored_len() as u64);
     }
-}
 
-impl<'a> Iterator for BlockIterator<'a> {
-    type Item = &'a [u8];
+    let games_written = writer.games_written();
+    writer.into_inner()?;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.offset + 4 > self.data.len() {
-            return None;
+    progress_bar.finish_with_message(format!(
+        "Exported {} games",
+        games_written.to_formatted_string(&Locale::en)
+    ));
+
+    Ok(())
+}
+
+fn show_game(input_file: &str, n: u64) -> Result<()> {
+    let reader = ArchiveReader::open(input_file)?;
+    let Some(found) = reader.block_of_game(n) else {
+        match reader.game_count() {
+            Some(count) => bail!(
+                "{input_file} has {} games, so there's no game {n}",
+                count.to_formatted_string(&Locale::en)
+            ),
+            None => bail!("{input_file} has no game {n}"),
         }
+    };
 
-        // Read the 4-byte block length (little-endian u32)
-        let length_bytes = &self.data[self.offset..self.offset + 4];
-        let block_length = u32::from_le_bytes([
-            length_bytes[0],
-            length_bytes[1],
-            length_bytes[2],
-            length_bytes[3],
-        ]) as usize;
-
-        // Move past the length header
-        self.offset += 4;
-
-        // Check if we have enough bytes for the block data
-        if self.offset + block_length > self.data.len() {
-            return None;
+    let (block, position) = found?;
+    let block = block.decode()?;
+    let Some(game) = block.game(position) else {
+        bail!("{input_file} has no game {n}");
+    };
+
+    let mut writer = PgnWriter::new(std::io::stdout().lock());
+    writer.write_game(&game?)?;
+    drop(writer.into_inner()?);
+    Ok(())
+}
+
+fn verify_file(input_file: &str) -> Result<()> {
+    println!("Verifying chess binary file: {input_file}");
+
+    let reader = ArchiveReader::open(input_file)?;
+    match reader.header() {
+        Some(header) => println!("Format version: {}.{}", header.major, header.minor),
+        None => println!("Format version: none (written before file headers)"),
+    }
+    println!(
+        "Block checksums: {}",
+        if reader.has_checksums() { "yes" } else { "no" }
+    );
+    println!(
+        "Compression dictionary: {}",
+        reader.dictionary().map_or_else(
+            || "no".to_owned(),
+            |dictionary| format!(
+                "{} bytes",
+                dictionary.len().to_formatted_string(&Locale::en)
+            )
+        )
+    );
+    println!(
+        "Block index: {}",
+        if reader.index().is_some() {
+            "yes"
+        } else {
+            "no"
         }
+    );
+
+    let report = reader.verify();
+    println!("Valid blocks: {}", report.valid_blocks);
+    println!(
+        "Valid games: {}",
+        report.valid_games.to_formatted_string(&Locale::en)
+    );
+    for error in &report.errors {
+        println!("Error: {error}");
+    }
+    println!(
+        "Last good offset: {} of {} bytes",
+        report.last_good_offset.to_formatted_string(&Locale::en),
+        reader.data().len().to_formatted_string(&Locale::en)
+    );
+
+    if !report.is_ok() {
+        bail!("{input_file} has {} problem(s)", report.errors.len());
+    }
+    println!("No problems found");
+    Ok(())
+}
+
+fn repair_file(input_file: &str, output_file: &str, compress_level: Option<i32>) -> Result<()> {
+    if Path::new(input_file) == Path::new(output_file) {
+        bail!("Repairing a file in place isn't supported, pick a different output file");
+    }
+    println!("Reading from {input_file}");
+    println!("Writing to {output_file}");
+
+    let file = File::open(input_file)?;
+    // Safety: the damaged archive is only ever read, and must not be modified while it's being repaired.
+    let data = unsafe { Mmap::map(&file)? };
 
-        let block_data = &self.data[self.offset..self.offset + block_length];
-        self.offset += block_length;
+    let mut serializer = Serializer::new(BufWriter::new(File::create(output_file)?));
+    serializer.set_compression_level(compress_level);
+    let report = repair(&data, &mut serializer)?;
 
-        Some(block_data)
+    for region in &report.damaged {
+        println!(
+            "Dropped {} bytes at offset {}: {}",
+            region.length.to_formatted_string(&Locale::en),
+            region.offset.to_formatted_string(&Locale::en),
+            region.error
+        );
     }
+    println!("Blocks kept: {}", report.kept_blocks);
+    println!(
+        "Games kept: {}",
+        report.kept_games.to_formatted_string(&Locale::en)
+    );
+    println!(
+        "Bytes lost: {} of {}",
+        report.bytes_lost().to_formatted_string(&Locale::en),
+        data.len().to_formatted_string(&Locale::en)
+    );
+
+    Ok(())
 }
 
-fn get_games_from_block(
-    block_data: &[u8],
-) -> Result<planus::vectors::Iter<'_, Result<generated_chess::GameRef<'_>, planus::Error>>> {
-    let block = BlockRef::read_as_root(block_data)?;
-    let archive = block.archive()?;
+fn merge_files(
+    inputs: &[String],
+    output_file: &str,
+    output_settings: &OutputSettings,
+) -> Result<()> {
+    // The inputs are memory-mapped, so writing over one of them while it's read would pull it out from under us.
+    let output_path = std::fs::canonicalize(output_file).ok();
+    println!("Merging {} archives", inputs.len());
+    let mut archives = Vec::with_capacity(inputs.len());
+    for (n, input) in inputs.iter().enumerate() {
+        if output_path.is_some() && std::fs::canonicalize(input).ok() == output_path {
+            bail!("Merging into one of the inputs isn't supported, pick a different output file");
+        }
+        println!("  Archive {n}: {input}");
+        archives.push(ArchiveReader::open(input)?);
+    }
+    println!("Writing to {output_file}");
+
+    let mut serializer = Serializer::new(BufWriter::new(File::create(output_file)?));
+    output_settings.apply(&mut serializer);
+    let report = merge(&archives, &mut serializer)?;
 
-    let generated_chess::ArchiveTypeRef::Archive(archive_ref) = archive;
-    let games = archive_ref.games()?;
-    Ok(games.iter())
+    println!("Blocks copied whole: {}", report.copied_blocks);
+    println!(
+        "Games re-packed: {}",
+        report.repacked_games.to_formatted_string(&Locale::en)
+    );
+    println!(
+        "Games written: {}",
+        report.games.to_formatted_string(&Locale::en)
+    );
+    println!("Blocks written: {}", serializer.block_stats().len());
+    println!(
+        "Bytes written: {}",
+        serializer.bytes_written().to_formatted_string(&Locale::en)
+    );
+
+    Ok(())
 }
 
 fn is_white_win(game: &generated_chess::GameRef) -> Result<bool> {
-    use crate::utils::move_ref_to_san;
+    use chessb::utils::{move_ref_to_san, start_position};
 
-    let mut chess = shakmaty::Chess::default();
+    let mut chess = start_position(game.start_position()?)?;
 
     for move_item in game.moves()? {
         let move_ref = move_item?;
         let san = move_ref_to_san(&move_ref)?;
-        let mv = san.to_move(&chess)?;
+        let mv = san.san.to_move(&chess)?;
         chess = chess.play(mv)?;
     }
 
@@ -207,19 +1033,21 @@ fn is_white_win(game: &generated_chess::GameRef) -> Result<bool> {
 fn read_file(input_file: &str) -> Result<()> {
     println!("Reading chess binary file: {input_file}");
 
-    let file = File::open(input_file)?;
-    let mmap = unsafe { Mmap::map(&file)? };
-
-    // First pass: count total games for progress bar
-    let (block_count, total_games): (usize, usize) = BlockIterator::new(&mmap)
-        .par_bridge()
-        .map(|block_data| {
-            (
-                1,
-                get_games_from_block(block_data).map_or(0, Iterator::count),
-            )
-        })
-        .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+    let reader = ArchiveReader::open(input_file)?;
+
+    // First pass: count total games for progress bar, straight from the block index if there is one
+    let (block_count, total_games) = if let Some(index) = reader.index() {
+        let total_games = index.iter().map(|entry| entry.game_count as usize).sum();
+        (index.len(), total_games)
+    } else {
+        let mut block_count = 0;
+        let mut total_games = 0;
+        for block in reader.blocks() {
+            block_count += 1;
+            total_games += block?.game_count()?;
+        }
+        (block_count, total_games)
+    };
 
     println!("Total blocks: {block_count}");
     println!("Total games: {total_games}");
@@ -233,22 +1061,24 @@ fn read_file(input_file: &str) -> Result<()> {
     )?);
     moves_progress_bar.set_message("Calculating average moves");
 
-    let total_moves: usize = BlockIterator::new(&mmap)
-        .par_bridge()
-        .flat_map_iter(|block_data| {
-            get_games_from_block(block_data).unwrap_or_else(|_| planus::Vector::new_empty().iter())
-        })
-        .filter_map(std::result::Result::ok)
-        .map(|game| {
-            moves_progress_bar.inc(1);
-            game.moves().map_or(0, |moves| moves.len())
+    let total_moves: usize = reader
+        .par_blocks()
+        .map(|block| -> Result<usize> {
+            let block = block?.decode()?;
+            let mut moves = 0;
+            for game in block.games()? {
+                moves += game?.moves()?.len();
+                moves_progress_bar.inc(1);
+            }
+            Ok(moves)
         })
-        .sum();
+        .try_reduce(|| 0, |a, b| Ok(a + b))?;
 
     moves_progress_bar.finish_with_message("Average moves calculation complete");
 
+    #[allow(clippy::cast_precision_loss)]
     let average_moves_per_game = total_moves as f64 / total_games as f64;
-    println!("Average moves per game: {:.2}", average_moves_per_game);
+    println!("Average moves per game: {average_moves_per_game:.2}");
 
     // Set up progress bar for game analysis
     let progress_bar = ProgressBar::new(total_games as u64);
@@ -258,17 +1088,18 @@ fn read_file(input_file: &str) -> Result<()> {
     progress_bar.set_message("Analyzing games");
 
     // Third pass: analyze games with progress tracking
-    let white_wins = BlockIterator::new(&mmap)
-        .par_bridge()
-        .flat_map_iter(|block_data| {
-            get_games_from_block(block_data).unwrap_or_else(|_| planus::Vector::new_empty().iter())
-        })
-        .filter_map(std::result::Result::ok)
-        .filter(|game| {
-            progress_bar.inc(1);
-            is_white_win(game).unwrap_or(false)
+    let white_wins = reader
+        .par_blocks()
+        .map(|block| -> Result<usize> {
+            let block = block?.decode()?;
+            let mut white_wins = 0;
+            for game in block.games()? {
+                white_wins += usize::from(is_white_win(&game?).unwrap_or(false));
+                progress_bar.inc(1);
+            }
+            Ok(white_wins)
         })
-        .count();
+        .try_reduce(|| 0, |a, b| Ok(a + b))?;
 
     let elapsed = start_time.elapsed();
     progress_bar.finish_with_message(format!(
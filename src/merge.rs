@@ -0,0 +1,62 @@
+use std::io::Write;
+
+use anyhow::{Context, Result};
+
+use crate::{reader::ArchiveReader, serializer::Serializer};
+
+/// What `merge` did with the blocks of the archives it merged.
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    /// Number of full blocks that were copied over without re-encoding their games.
+    pub copied_blocks: usize,
+    /// Number of games re-encoded from blocks that weren't full.
+    pub repacked_games: u64,
+    /// Number of games in the merged archive.
+    pub games: u64,
+}
+
+/// Merges several archives into one, keeping the games in order.
+///
+/// Blocks that are as full as the ones the serializer is set up to write are copied over whole (compressed the way
+/// the serializer compresses blocks), so merging large archives stays cheap. The games of every other block, like
+/// the small one at the end of most archives, are re-encoded with `Serializer::add_game_ref` and packed into full
+/// blocks, with their moves and strings deduplicated again. The merged archive is finished with a block index
+/// covering every game.
+///
+/// Every block is fully verified before it's used, so damaged archives should be repaired first.
+///
+/// # Errors
+///
+/// Returns an error if a block of any archive is truncated or corrupted, or if writing the merged archive fails.
+pub fn merge<W: Write>(
+    archives: &[ArchiveReader<'_>],
+    serializer: &mut Serializer<W>,
+) -> Result<MergeReport> {
+    let mut report = MergeReport::default();
+    for (n, archive) in archives.iter().enumerate() {
+        for block in archive.blocks() {
+            let (block, game_count) = block
+                .and_then(|block| {
+                    block.check_checksum()?;
+                    let block = block.decode()?;
+                    let game_count = block.verify()?;
+                    Ok((block, game_count))
+                })
+                .with_context(|| format!("Archive {n} is damaged, repair it before merging"))?;
+
+            if serializer.can_add_whole_block(game_count, block.data().len()) {
+                serializer.add_encoded_block(block.data(), game_count)?;
+                report.copied_blocks += 1;
+            } else {
+                for game in block.games()? {
+                    serializer.add_game_ref(&game?)?;
+                }
+                report.repacked_games += game_count as u64;
+            }
+            report.games += game_count as u64;
+        }
+    }
+
+    serializer.finish()?;
+    Ok(report)
+}
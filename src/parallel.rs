@@ -0,0 +1,431 @@
+use std::{
+    io::{self, Read, Write},
+    sync::mpsc,
+    thread,
+};
+
+use anyhow::Result;
+use rayon::prelude::*;
+
+use crate::{
+    converter::{ConversionError, ConversionStats, Converter, ErrorPolicy},
+    serializer::{BuiltBlock, Serializer},
+};
+
+/// Default amount of PGN handed to a worker at a time.
+const CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// How many times the chunk size a chunk can grow to while looking for the start of a game to end it at.
+const MAX_CHUNK_GROWTH: usize = 8;
+
+/// A run of whole games from the PGN input.
+struct Chunk {
+    data: Vec<u8>,
+    /// Byte offset of the chunk in the PGN input.
+    offset: u64,
+}
+
+/// What the reading thread hands over to be converted.
+enum Input<R: Read> {
+    /// A run of whole games, to be converted on the thread pool.
+    Chunk(Chunk),
+    /// The rest of the input, which couldn't be cut into chunks, to be converted sequentially.
+    Rest(ChunkReader<R>),
+}
+
+/// What a worker made of a chunk.
+struct ChunkOutput {
+    blocks: Vec<BuiltBlock>,
+    games_read: usize,
+    games_written: usize,
+    skipped_games: Vec<ConversionError>,
+}
+
+/// Converts PGN to a chess binary on several threads.
+///
+/// The PGN input is read ahead on a thread of its own and cut into chunks at game boundaries, so reading overlaps
+/// with converting. Chunks are converted on the rayon thread pool, each by its own `Converter` with its own
+/// `Builder` and move map, and the blocks they build are written out in the order of the input. The output is the
+/// same whatever the number of threads, though it isn't the same as the sequential `Converter`'s: blocks never span
+/// two chunks, so every chunk ends a block.
+///
+/// Block size limits, compression and the dictionary are taken from the serializer, as with `Converter`.
+///
+/// A new game is recognized by a tag pair at the start of a line, right after a blank line, outside any comment.
+/// Games without tags are converted fine, but can't be split apart. Once a chunk has grown to 8 times the chunk size
+/// without a game to end it at, the rest of the input is converted on a single thread as it's read, rather than
+/// held in memory.
+pub struct ParallelConverter<W: Write, R: Read> {
+    reader: R,
+    serializer: Serializer<W>,
+    validate: bool,
+    error_policy: ErrorPolicy,
+    chunk_size: usize,
+    threads: Option<usize>,
+}
+
+impl<W: Write, R: Read + Send> ParallelConverter<W, R> {
+    /// Creates a new parallel converter from the given reader and serializer.
+    ///
+    /// Like `Converter`, games that can't be converted abort the conversion by default.
+    pub fn new(reader: R, serializer: Serializer<W>) -> Self {
+        Self {
+            reader,
+            serializer,
+            validate: false,
+            error_policy: ErrorPolicy::default(),
+            chunk_size: CHUNK_SIZE,
+            threads: None,
+        }
+    }
+
+    /// Enables or disables validation, see `Converter::set_validation`.
+    pub const fn set_validation(&mut self, validate: bool) {
+        self.validate = validate;
+    }
+
+    /// Sets what happens when a game can't be converted.
+    pub const fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.error_policy = error_policy;
+    }
+
+    /// Sets roughly how many bytes of PGN are converted at a time by one thread. Defaults to 16 MiB.
+    ///
+    /// Every chunk ends a block, so this also caps the size of blocks.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.max(1);
+    }
+
+    /// Converts on a pool of its own with this many threads, instead of on rayon's global pool.
+    pub const fn set_threads(&mut self, threads: usize) {
+        self.threads = Some(threads);
+    }
+
+    /// Converts every game, finishes the archive and returns the output stream along with the conversion
+    /// statistics.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading or parsing the PGN fails, or if writing to the output fails. Under
+    /// `ErrorPolicy::Abort`, a game that can't be converted returns a `ConversionError`. Either way, the archive
+    /// is finished with the chunks before the failing one.
+    pub fn convert(self) -> Result<(W, ConversionStats)> {
+        let Self {
+            reader,
+            mut serializer,
+            validate,
+            error_policy,
+            chunk_size,
+            threads,
+        } = self;
+        let pool = threads
+            .map(|threads| rayon::ThreadPoolBuilder::new().num_threads(threads).build())
+            .transpose()?;
+        let batch_len = pool.as_ref().map_or_else(
+            rayon::current_num_threads,
+            rayon::ThreadPool::current_num_threads,
+        ) * 2;
+
+        let mut stats = ConversionStats::default();
+        let mut rest = None;
+        // The next batch of chunks is read while the current one is converted.
+        let (sender, chunks) = mpsc::sync_channel(batch_len);
+        let result = thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut reader = ChunkReader {
+                    reader,
+                    buffer: vec![],
+                    offset: 0,
+                    eof: false,
+                };
+                let max_size = chunk_size.saturating_mul(MAX_CHUNK_GROWTH);
+                // Stops at the end of the input, at the first error, once chunks aren't wanted anymore, or once
+                // the input can't be cut into chunks, handing over the rest of it.
+                while let Some(chunk) = reader.next_chunk(chunk_size, max_size).transpose() {
+                    let failed = chunk.is_err();
+                    if sender.send(chunk.map(Input::Chunk)).is_err() || failed {
+                        return;
+                    }
+                }
+                if !reader.is_done() {
+                    let _ = sender.send(Ok(Input::Rest(reader)));
+                }
+            });
+
+            let result = loop {
+                // Take a batch of chunks, convert them in parallel, then write them out in order.
+                let batch = match chunks
+                    .iter()
+                    .take(batch_len)
+                    .collect::<io::Result<Vec<_>>>()
+                {
+                    Ok(batch) if batch.is_empty() => break Ok(()),
+                    Ok(batch) => batch,
+                    Err(err) => break Err(err.into()),
+                };
+
+                let jobs: Vec<_> = batch
+                    .into_iter()
+                    .filter_map(|input| match input {
+                        Input::Chunk(chunk) => Some(chunk),
+                        Input::Rest(reader) => {
+                            rest = Some(reader);
+                            None
+                        }
+                    })
+                    .map(|chunk| (chunk, Serializer::collecting(&serializer)))
+                    .collect();
+                let convert = || {
+                    jobs.into_par_iter()
+                        .map(|(chunk, serializer)| {
+                            convert_chunk(&chunk, serializer, validate, error_policy)
+                        })
+                        .collect::<Vec<_>>()
+                };
+                let outputs = match &pool {
+                    Some(pool) => pool.install(convert),
+                    None => convert(),
+                };
+
+                if let Err(err) = write_outputs(&mut serializer, outputs, &mut stats) {
+                    break Err(err);
+                }
+            };
+            // Let the reading thread know it can stop, so the scope doesn't wait for it to read everything.
+            drop(chunks);
+            result
+        });
+        let (mut serializer, result) = match (result, rest) {
+            (Ok(()), Some(rest)) => {
+                convert_rest(rest, serializer, validate, error_policy, &mut stats)?
+            }
+            (result, _) => (serializer, result),
+        };
+
+        // Finish the archive even after an error, like a dropped `Converter` would, so the games that made it
+        // are readable.
+        if let Err(err) = result {
+            let _ = serializer.finish();
+            return Err(err);
+        }
+        serializer.finish()?;
+        stats.blocks = serializer.block_stats().to_vec();
+        stats.bytes_written = serializer.bytes_written();
+        Ok((serializer.into_inner()?, stats))
+    }
+}
+
+/// Writes the blocks of converted chunks in order, stopping at the first chunk that failed.
+fn write_outputs<W: Write>(
+    serializer: &mut Serializer<W>,
+    outputs: Vec<Result<ChunkOutput>>,
+    stats: &mut ConversionStats,
+) -> Result<()> {
+    for output in outputs {
+        let output = output.map_err(|err| counted_from(err, stats.games_read))?;
+        for block in &output.blocks {
+            serializer.add_built_block(block)?;
+        }
+        add_stats(stats, output);
+    }
+    Ok(())
+}
+
+/// Adds the games of a converted chunk to the totals.
+fn add_stats(stats: &mut ConversionStats, output: ChunkOutput) {
+    // Game indices count from the start of the chunk, but should count from the start of the input.
+    stats
+        .skipped_games
+        .extend(output.skipped_games.into_iter().map(|mut error| {
+            error.game_index += stats.games_read;
+            error
+        }));
+    stats.games_read += output.games_read;
+    stats.games_written += output.games_written;
+}
+
+/// Makes the game index of a `ConversionError` count from the start of the input rather than from the start of its
+/// chunk, which came after `games_before` games.
+fn counted_from(mut err: anyhow::Error, games_before: usize) -> anyhow::Error {
+    if let Some(error) = err.downcast_mut::<ConversionError>() {
+        error.game_index += games_before;
+    }
+    err
+}
+
+/// Converts the rest of the input on the calling thread, reading it as it goes, straight into the archive. Returns
+/// the serializer along with how the conversion went, so the archive can be finished either way.
+fn convert_rest<W: Write, R: Read>(
+    rest: ChunkReader<R>,
+    serializer: Serializer<W>,
+    validate: bool,
+    error_policy: ErrorPolicy,
+    stats: &mut ConversionStats,
+) -> Result<(Serializer<W>, Result<()>)> {
+    let reader = io::Cursor::new(rest.buffer).chain(rest.reader);
+    let mut converter = Converter::new(reader, serializer);
+    converter.set_validation(validate);
+    converter.set_error_policy(error_policy);
+    converter.set_byte_offset(rest.offset);
+    let result = loop {
+        match converter.next_game() {
+            Ok(true) => {}
+            Ok(false) => break Ok(()),
+            Err(err) => break Err(err),
+        }
+    };
+
+    let games_before = stats.games_read;
+    let rest_stats = converter.stats();
+    add_stats(
+        stats,
+        ChunkOutput {
+            blocks: vec![],
+            games_read: rest_stats.games_read,
+            games_written: rest_stats.games_written,
+            skipped_games: rest_stats.skipped_games,
+        },
+    );
+    let serializer = converter.into_serializer()?;
+    Ok((
+        serializer,
+        result.map_err(|err| counted_from(err, games_before)),
+    ))
+}
+
+/// Converts a chunk into blocks with a serializer from `Serializer::collecting`.
+fn convert_chunk(
+    chunk: &Chunk,
+    serializer: Serializer<io::Sink>,
+    validate: bool,
+    error_policy: ErrorPolicy,
+) -> Result<ChunkOutput> {
+    let mut converter = Converter::new(chunk.data.as_slice(), serializer);
+    converter.set_validation(validate);
+    converter.set_error_policy(error_policy);
+    converter.set_byte_offset(chunk.offset);
+    while converter.next_game()? {}
+
+    let stats = converter.stats();
+    let blocks = converter.into_serializer()?.take_built_blocks();
+    Ok(ChunkOutput {
+        blocks,
+        games_read: stats.games_read,
+        games_written: stats.games_written,
+        skipped_games: stats.skipped_games,
+    })
+}
+
+/// Reads PGN in chunks that end at game boundaries.
+struct ChunkReader<R: Read> {
+    reader: R,
+    /// Data that's been read but not handed out yet.
+    buffer: Vec<u8>,
+    /// Byte offset of the start of `buffer` in the input.
+    offset: u64,
+    eof: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Reads the next chunk of at least `size` bytes (unless the input ends first), up to the start of the next
+    /// game. Returns `None` at the end of the input, or once more than `max_size` bytes have been read without
+    /// finding the start of a game, leaving them in the buffer; `is_done` tells the two apart.
+    fn next_chunk(&mut self, size: usize, max_size: usize) -> io::Result<Option<Chunk>> {
+        // Chunks start at the start of a game, so never inside a comment.
+        let mut scanner = GameStartScanner::default();
+        let end = loop {
+            self.fill(scanner.scanned.max(size) + size)?;
+            if let Some(start) = scanner.next_game_start(&self.buffer, size) {
+                break start;
+            }
+            if self.eof {
+                break self.buffer.len();
+            }
+            if self.buffer.len() >= max_size {
+                return Ok(None);
+            }
+            // No game starts in what's been read so far, so keep going.
+        };
+        if end == 0 {
+            return Ok(None);
+        }
+
+        let rest = self.buffer.split_off(end);
+        let chunk = Chunk {
+            data: std::mem::replace(&mut self.buffer, rest),
+            offset: self.offset,
+        };
+        self.offset += end as u64;
+        Ok(Some(chunk))
+    }
+
+    /// Whether the whole input has been handed out in chunks.
+    const fn is_done(&self) -> bool {
+        self.eof && self.buffer.is_empty()
+    }
+
+    /// Reads until the buffer holds at least `len` bytes or the input ends.
+    fn fill(&mut self, len: usize) -> io::Result<()> {
+        if !self.eof && self.buffer.len() < len {
+            let wanted = (len - self.buffer.len()) as u64;
+            let read = (&mut self.reader)
+                .take(wanted)
+                .read_to_end(&mut self.buffer)?;
+            self.eof = (read as u64) < wanted;
+        }
+        Ok(())
+    }
+}
+
+/// Looks for the start of the next game in PGN that's read a piece at a time, keeping track of comments and tag
+/// values so that a `[` inside one isn't taken for the start of a game.
+#[derive(Default)]
+struct GameStartScanner {
+    /// Number of bytes scanned so far.
+    scanned: usize,
+    state: ScanState,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ScanState {
+    #[default]
+    Text,
+    /// Inside a `{...}` comment, which can span several lines.
+    BraceComment,
+    /// Inside a `;` comment, which runs to the end of the line.
+    LineComment,
+    /// Inside a quoted tag value.
+    TagValue,
+    /// Right after a backslash in a quoted tag value.
+    TagValueEscape,
+}
+
+impl GameStartScanner {
+    /// Finds the first game that starts at or after `from`: a `[` at the start of a line that follows a blank line,
+    /// outside any comment. Picks up scanning where the last call left off, so `data` has to be the same data as
+    /// before, with more read onto the end.
+    fn next_game_start(&mut self, data: &[u8], from: usize) -> Option<usize> {
+        while let Some(&byte) = data.get(self.scanned) {
+            let i = self.scanned;
+            if self.state == ScanState::Text
+                && byte == b'['
+                && i >= from.max(1)
+                && (data[..i].ends_with(b"\n\n") || data[..i].ends_with(b"\n\r\n"))
+            {
+                return Some(i);
+            }
+            self.state = match (self.state, byte) {
+                (ScanState::Text, b'{') => ScanState::BraceComment,
+                (ScanState::Text, b';') => ScanState::LineComment,
+                (ScanState::Text, b'"') | (ScanState::TagValueEscape, _) => ScanState::TagValue,
+                (ScanState::BraceComment, b'}')
+                | (ScanState::LineComment | ScanState::TagValue, b'\n')
+                | (ScanState::TagValue, b'"') => ScanState::Text,
+                (ScanState::TagValue, b'\\') => ScanState::TagValueEscape,
+                (state, _) => state,
+            };
+            self.scanned += 1;
+        }
+        None
+    }
+}
@@ -0,0 +1,182 @@
+use std::io::Write;
+
+use anyhow::Result;
+use planus::ReadAsRoot;
+use shakmaty::{Color, Position};
+
+use crate::{
+    generated_chess::{ArchiveTypeRef, BlockRef, GameInfoRef, GameRef, GameResult},
+    utils::{move_ref_to_san, start_position},
+};
+
+/// Lines of movetext are wrapped before they reach this many characters, as the PGN export format asks for.
+const MAX_LINE_LENGTH: usize = 80;
+
+/// Writes games from a chess binary back out as PGN.
+///
+/// Games are written in the PGN export format: the seven tag roster (with `?` for anything the archive doesn't
+/// store), Elo tags when known, `[SetUp]`/`[FEN]` for games that don't start from the standard position, then
+/// numbered movetext ending in the result token.
+pub struct PgnWriter<W: Write> {
+    writer: W,
+    games_written: usize,
+}
+
+impl<W: Write> PgnWriter<W> {
+    /// Creates a new PGN writer that writes to the given writer.
+    ///
+    /// Games are written one small write at a time, so you probably want to wrap files in a `BufWriter`.
+    pub const fn new(writer: W) -> Self {
+        Self {
+            writer,
+            games_written: 0,
+        }
+    }
+
+    /// Writes every game in a block (without its length prefix). Returns the number of games written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block can't be decoded, or if writing fails.
+    pub fn write_block(&mut self, block_data: &[u8]) -> Result<usize> {
+        let block = BlockRef::read_as_root(block_data)?;
+        let ArchiveTypeRef::Archive(archive) = block.archive()?;
+
+        let mut count = 0;
+        for game in archive.games()? {
+            self.write_game(&game?)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Writes a single game.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game can't be decoded (including a start position that isn't a legal position),
+    /// or if writing fails.
+    pub fn write_game(&mut self, game: &GameRef<'_>) -> Result<()> {
+        let result = result_token(game.result()?);
+        let fen = game.start_position()?;
+
+        self.write_tags(game.info()?, result, fen)?;
+        writeln!(self.writer)?;
+        self.write_movetext(game, result, fen)?;
+        writeln!(self.writer)?;
+
+        self.games_written += 1;
+        Ok(())
+    }
+
+    fn write_tags(
+        &mut self,
+        info: Option<GameInfoRef<'_>>,
+        result: &str,
+        fen: Option<&str>,
+    ) -> Result<()> {
+        let (mut event, mut site, mut white, mut black) = (None, None, None, None);
+        let (mut white_elo, mut black_elo) = (0, 0);
+        if let Some(info) = info {
+            event = info.event()?;
+            site = info.site()?;
+            white = info.white_player()?;
+            black = info.black_player()?;
+            white_elo = info.white_elo()?;
+            black_elo = info.black_elo()?;
+        }
+
+        self.write_tag("Event", event.unwrap_or("?"))?;
+        self.write_tag("Site", site.unwrap_or("?"))?;
+        self.write_tag("Date", "????.??.??")?;
+        self.write_tag("Round", "?")?;
+        self.write_tag("White", white.unwrap_or("?"))?;
+        self.write_tag("Black", black.unwrap_or("?"))?;
+        self.write_tag("Result", result)?;
+
+        // Elos of 0 mean the rating wasn't known.
+        if white_elo != 0 {
+            self.write_tag("WhiteElo", &white_elo.to_string())?;
+        }
+        if black_elo != 0 {
+            self.write_tag("BlackElo", &black_elo.to_string())?;
+        }
+
+        if let Some(fen) = fen {
+            self.write_tag("SetUp", "1")?;
+            self.write_tag("FEN", fen)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_tag(&mut self, name: &str, value: &str) -> Result<()> {
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(self.writer, "[{name} \"{escaped}\"]")?;
+        Ok(())
+    }
+
+    fn write_movetext(&mut self, game: &GameRef<'_>, result: &str, fen: Option<&str>) -> Result<()> {
+        let position = start_position(fen)?;
+        let mut turn = position.turn();
+        let mut move_number = position.fullmoves().get();
+
+        let mut line = String::new();
+        for (index, move_ref) in game.moves()?.iter().enumerate() {
+            let san = move_ref_to_san(&move_ref?)?;
+            let token = match turn {
+                Color::White => format!("{move_number}. {san}"),
+                Color::Black if index == 0 => format!("{move_number}... {san}"),
+                Color::Black => san.to_string(),
+            };
+            self.push_token(&mut line, &token)?;
+
+            if turn == Color::Black {
+                move_number += 1;
+            }
+            turn = !turn;
+        }
+        self.push_token(&mut line, result)?;
+
+        writeln!(self.writer, "{line}")?;
+        Ok(())
+    }
+
+    /// Adds a token to the current movetext line, writing the line out first if the token wouldn't fit.
+    fn push_token(&mut self, line: &mut String, token: &str) -> Result<()> {
+        if !line.is_empty() && line.len() + 1 + token.len() >= MAX_LINE_LENGTH {
+            writeln!(self.writer, "{line}")?;
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(token);
+        Ok(())
+    }
+
+    /// Gets the number of games written so far.
+    pub const fn games_written(&self) -> usize {
+        self.games_written
+    }
+
+    /// Flushes and returns the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing fails.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Gets the PGN result token for a game result.
+const fn result_token(result: GameResult) -> &'static str {
+    match result {
+        GameResult::WhiteWin => "1-0",
+        GameResult::BlackWin => "0-1",
+        GameResult::Draw => "1/2-1/2",
+        GameResult::Unknown => "*",
+    }
+}
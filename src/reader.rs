@@ -0,0 +1,1024 @@
+use std::{borrow::Cow, fmt, fs::File, io::Read, iter, ops::Range, path::Path};
+
+use anyhow::Result;
+use memmap2::Mmap;
+use planus::ReadAsRoot;
+use rayon::iter::{Either, IntoParallelRefIterator, ParallelBridge, ParallelIterator};
+
+use crate::{
+    generated_chess::{
+        ArchiveType, ArchiveTypeRef, Block, BlockIndexEntry, BlockIndexRef, BlockRef, Game, GameRef,
+    },
+    header::{
+        ENCODING_NONE, ENCODING_ZSTD, ENCODING_ZSTD_DICTIONARY, FLAG_BLOCK_CHECKSUMS,
+        FLAG_BLOCK_ENCODINGS, FOOTER_LEN, FileHeader, HEADER_LEN, INDEX_MAGIC,
+    },
+};
+
+/// A problem with the structure of an archive.
+///
+/// Offsets are byte offsets into the archive, pointing at the start of the block's length prefix.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The archive starts with the magic bytes, but ends before the rest of the file header (or its dictionary).
+    TruncatedHeader,
+    /// The archive was written with a major format version this library can't read.
+    UnsupportedVersion { major: u16, minor: u16 },
+    /// The archive uses features this library doesn't know about.
+    UnsupportedFlags { flags: u32 },
+    /// The archive ends in the middle of a block's length prefix.
+    TruncatedLength { offset: usize },
+    /// The archive ends before the end of a block.
+    TruncatedBlock {
+        offset: usize,
+        length: usize,
+        available: usize,
+    },
+    /// A block (or a game inside it) isn't a valid `Block` `FlatBuffer`.
+    InvalidBlock {
+        offset: usize,
+        source: planus::Error,
+    },
+    /// A block's data doesn't match its stored checksum.
+    ChecksumMismatch {
+        offset: usize,
+        expected: u32,
+        actual: u32,
+    },
+    /// A block is stored with an encoding this library doesn't know about.
+    UnsupportedEncoding { offset: usize, encoding: u32 },
+    /// A compressed block couldn't be decompressed.
+    Decompression {
+        offset: usize,
+        source: std::io::Error,
+    },
+    /// A compressed block was read by a method that borrows games straight from the archive. Decompress it with
+    /// `RawBlock::decode`, or read it with the `_owned` methods of `ArchiveReader`, instead.
+    CompressedBlock { offset: usize },
+    /// The block index doesn't list the block at this offset, or lists it with a different number of games.
+    IndexMismatch { offset: usize },
+    /// There's data after the marker that ends the blocks, but no block index.
+    TrailingData { offset: usize },
+    /// The archive ends with a footer, but the block index it points at is damaged or doesn't add up. The offset is
+    /// the one stored in the footer. Only `verify` reports this: readers ignore such an index and walk the blocks
+    /// instead.
+    InvalidIndex {
+        offset: u64,
+        source: Option<planus::Error>,
+    },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TruncatedHeader => write!(f, "archive ends inside the file header"),
+            Self::UnsupportedVersion { major, minor } => write!(
+                f,
+                "archive uses format version {major}.{minor}, but only version {}.x is supported",
+                crate::header::FORMAT_VERSION_MAJOR
+            ),
+            Self::UnsupportedFlags { flags } => {
+                write!(f, "archive uses unsupported feature flags {flags:#010x}")
+            }
+            Self::TruncatedLength { offset } => {
+                write!(
+                    f,
+                    "archive ends inside the length prefix of the block at offset {offset}"
+                )
+            }
+            Self::TruncatedBlock {
+                offset,
+                length,
+                available,
+            } => write!(
+                f,
+                "block at offset {offset} is {length} bytes long, but only {available} bytes are left"
+            ),
+            Self::InvalidBlock { offset, source } => {
+                write!(f, "block at offset {offset} is invalid: {source}")
+            }
+            Self::ChecksumMismatch {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "block at offset {offset} has checksum {actual:#010x}, but {expected:#010x} was stored"
+            ),
+            Self::UnsupportedEncoding { offset, encoding } => write!(
+                f,
+                "block at offset {offset} uses unsupported encoding {encoding}"
+            ),
+            Self::Decompression { offset, source } => {
+                write!(
+                    f,
+                    "block at offset {offset} can't be decompressed: {source}"
+                )
+            }
+            Self::CompressedBlock { offset } => write!(
+                f,
+                "block at offset {offset} is compressed, so its games can't be borrowed from the archive"
+            ),
+            Self::IndexMismatch { offset } => {
+                write!(f, "block index doesn't match the block at offset {offset}")
+            }
+            Self::TrailingData { offset } => write!(
+                f,
+                "unexpected data after the end of the blocks at offset {offset}"
+            ),
+            Self::InvalidIndex {
+                offset,
+                source: Some(source),
+            } => write!(f, "block index at offset {offset} is invalid: {source}"),
+            Self::InvalidIndex {
+                offset,
+                source: None,
+            } => {
+                write!(f, "block index at offset {offset} is invalid")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidBlock { source, .. }
+            | Self::InvalidIndex {
+                source: Some(source),
+                ..
+            } => Some(source),
+            Self::Decompression { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Where the bytes of an archive live.
+enum Data<'a> {
+    Borrowed(&'a [u8]),
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+/// Reads chess binary archives.
+///
+/// The reader gives zero-copy access to the games in an archive, either block by block, as one flat sequence of
+/// games, or in parallel across blocks with rayon. Compressed blocks can't be read in place: decompress them with
+/// `RawBlock::decode`, which keeps them in memory for as long as their games are read, or use the `_owned` methods,
+/// which decode every game into an owned `Game`. Structural problems (truncated or invalid blocks) are returned as
+/// `ReadError`s instead of silently ending iteration. Since blocks are found by walking their length prefixes,
+/// iteration stops after the first truncated block.
+///
+/// The file header and block index are checked when the reader is created. Archives written before the header
+/// existed are read as-is. If an archive has no index (because it's old, or its writer was interrupted), or its
+/// index is damaged, blocks are found by walking the length prefixes instead.
+pub struct ArchiveReader<'a> {
+    data: Data<'a>,
+    header: Option<FileHeader>,
+    dictionary: Option<Range<usize>>,
+    index: Option<Vec<BlockIndexEntry>>,
+}
+
+impl ArchiveReader<'static> {
+    /// Opens the archive at the given path by memory-mapping it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be opened or mapped, or if its file header isn't supported.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        // Safety: the archive is only ever read. As with any memory map, the file must not be truncated while
+        // it's mapped.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self::new(Data::Mapped(mmap))?)
+    }
+
+    /// Reads a whole archive from the given reader into memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails, or if the archive's file header isn't supported.
+    pub fn from_reader(mut reader: impl Read) -> Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Ok(Self::new(Data::Owned(data))?)
+    }
+}
+
+impl<'a> ArchiveReader<'a> {
+    /// Reads an archive that's already in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive's file header is truncated or uses a version this library can't read.
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self, ReadError> {
+        Self::new(Data::Borrowed(data))
+    }
+
+    fn new(data: Data<'a>) -> Result<Self, ReadError> {
+        let mut reader = Self {
+            data,
+            header: None,
+            dictionary: None,
+            index: None,
+        };
+        reader.header = FileHeader::read(reader.data())?;
+        if let Some(header) = reader.header {
+            reader.dictionary = header.read_dictionary(reader.data())?.map(|dictionary| {
+                let start = HEADER_LEN + 4;
+                start..start + dictionary.len()
+            });
+            // A damaged index only costs the shortcut: the blocks can still be found by walking them. `verify`
+            // reports the damage.
+            reader.index = read_index(reader.data()).ok().flatten();
+        }
+        Ok(reader)
+    }
+
+    /// Gets the archive's file header, or `None` if the archive was written before headers existed.
+    #[must_use]
+    pub const fn header(&self) -> Option<FileHeader> {
+        self.header
+    }
+
+    /// Whether the archive stores a checksum for every block.
+    #[must_use]
+    pub fn has_checksums(&self) -> bool {
+        self.header
+            .is_some_and(|header| header.has_block_checksums())
+    }
+
+    /// Gets the zstd dictionary stored after the file header, if the archive has one.
+    #[must_use]
+    pub fn dictionary(&self) -> Option<&[u8]> {
+        self.dictionary
+            .clone()
+            .map(|dictionary| &self.data()[dictionary])
+    }
+
+    /// Gets what's needed to read the archive's blocks.
+    #[must_use]
+    pub fn block_format(&self) -> BlockFormat<'_> {
+        BlockFormat {
+            flags: self.header.map_or(0, |header| header.flags),
+            dictionary: self.dictionary(),
+        }
+    }
+
+    /// Gets the byte offset of the first block.
+    #[must_use]
+    pub const fn blocks_offset(&self) -> usize {
+        match (&self.header, &self.dictionary) {
+            (_, Some(dictionary)) => dictionary.end,
+            (Some(_), None) => HEADER_LEN,
+            (None, None) => 0,
+        }
+    }
+
+    /// Gets the raw bytes of the archive.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        match &self.data {
+            Data::Borrowed(data) => data,
+            Data::Mapped(mmap) => mmap,
+            Data::Owned(data) => data,
+        }
+    }
+
+    /// Gets the archive's block index, or `None` if the archive doesn't have one.
+    #[must_use]
+    pub fn index(&self) -> Option<&[BlockIndexEntry]> {
+        self.index.as_deref()
+    }
+
+    /// Gets the number of blocks in the archive from the block index, without reading any blocks.
+    #[must_use]
+    pub fn block_count(&self) -> Option<usize> {
+        self.index().map(<[_]>::len)
+    }
+
+    /// Gets the number of games in the archive from the block index, without reading any blocks.
+    #[must_use]
+    pub fn game_count(&self) -> Option<u64> {
+        self.index().map(|index| {
+            index
+                .last()
+                .map_or(0, |entry| entry.first_game + u64::from(entry.game_count))
+        })
+    }
+
+    /// Gets the nth block of the archive. Uses the block index if there is one, otherwise walks the blocks before
+    /// it. Returns `None` if the archive has fewer blocks.
+    #[must_use]
+    pub fn block(&self, n: usize) -> Option<Result<RawBlock<'_>, ReadError>> {
+        self.index().map_or_else(
+            || self.blocks().nth(n),
+            |index| index.get(n).map(|entry| self.indexed_block(entry)),
+        )
+    }
+
+    /// Gets the nth game of the archive, counting from 0 across all blocks. Uses the block index to go straight to
+    /// the right block if there is one, otherwise walks the blocks before it (without decoding their games).
+    /// Returns `None` if the archive has fewer games.
+    ///
+    /// Games in compressed blocks can't be borrowed, and come back as `ReadError::CompressedBlock`. Use
+    /// `game_owned` for those, or decode the block from `block_of_game`.
+    #[must_use]
+    pub fn game(&self, n: u64) -> Option<Result<GameRef<'_>, ReadError>> {
+        match self.block_of_game(n)? {
+            Ok((block, position)) => block.game(position),
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    /// Like `game`, but decodes the game into an owned `Game`, decompressing its block if needed.
+    #[must_use]
+    pub fn game_owned(&self, n: u64) -> Option<Result<Game, ReadError>> {
+        let (block, position) = match self.block_of_game(n)? {
+            Ok(found) => found,
+            Err(err) => return Some(Err(err)),
+        };
+        let block = match block.decode() {
+            Ok(block) => block,
+            Err(err) => return Some(Err(err)),
+        };
+        let game = block.game(position)?;
+        Some(game.and_then(|game| Game::try_from(game).map_err(|source| block.invalid(source))))
+    }
+
+    /// Finds the block holding the nth game of the archive, along with the game's position in that block. Returns
+    /// `None` if the archive has fewer games.
+    #[must_use]
+    pub fn block_of_game(&self, n: u64) -> Option<Result<(RawBlock<'_>, usize), ReadError>> {
+        // The block holds game n, so the difference fits in its game count.
+        #[allow(clippy::cast_possible_truncation)]
+        if let Some(index) = self.index() {
+            let entry = index[..index.partition_point(|entry| entry.first_game <= n)].last()?;
+            if n - entry.first_game >= u64::from(entry.game_count) {
+                return None;
+            }
+            return Some(
+                self.indexed_block(entry)
+                    .map(|block| (block, (n - entry.first_game) as usize)),
+            );
+        }
+
+        let mut first_game = 0;
+        for block in self.blocks() {
+            let count = match block.and_then(|block| Ok((block, block.game_count()?))) {
+                Ok((block, count)) if n - first_game < count as u64 => {
+                    #[allow(clippy::cast_possible_truncation)]
+                    return Some(Ok((block, (n - first_game) as usize)));
+                }
+                Ok((_, count)) => count,
+                Err(err) => return Some(Err(err)),
+            };
+            first_game += count as u64;
+        }
+        None
+    }
+
+    fn indexed_block(&self, entry: &BlockIndexEntry) -> Result<RawBlock<'_>, ReadError> {
+        // Offsets were checked against the size of the archive when the index was read.
+        #[allow(clippy::cast_possible_truncation)]
+        read_block(self.data(), entry.offset as usize, self.block_format())
+    }
+
+    /// Iterates over the blocks in the archive, in order.
+    #[must_use]
+    pub fn blocks(&self) -> BlockIterator<'_> {
+        BlockIterator::starting_at(self.data(), self.blocks_offset(), self.block_format())
+    }
+
+    /// Iterates over the blocks in the archive in parallel. With a block index, blocks are split evenly across
+    /// rayon workers up front. Without one, each block is handed to a worker as soon as its length prefix has been
+    /// read.
+    #[must_use]
+    pub fn par_blocks(&self) -> impl ParallelIterator<Item = Result<RawBlock<'_>, ReadError>> {
+        self.index().map_or_else(
+            || Either::Right(self.blocks().par_bridge()),
+            |index| Either::Left(index.par_iter().map(|entry| self.indexed_block(entry))),
+        )
+    }
+
+    /// Checks the whole archive: walks every length prefix, verifies each block's checksum and contents (in
+    /// parallel), and compares the blocks against the block index if there is one.
+    #[must_use]
+    pub fn verify(&self) -> VerifyReport {
+        let mut blocks = self.blocks();
+        let mut raw_blocks = vec![];
+        let mut scan_error = None;
+        for block in blocks.by_ref() {
+            match block {
+                Ok(block) => raw_blocks.push(block),
+                Err(err) => scan_error = Some(err),
+            }
+        }
+        let results: Vec<_> = raw_blocks.par_iter().map(RawBlock::verify).collect();
+
+        let mut report = VerifyReport {
+            valid_blocks: 0,
+            valid_games: 0,
+            errors: vec![],
+            last_good_offset: self.blocks_offset(),
+        };
+        for (position, (block, result)) in raw_blocks.iter().zip(results).enumerate() {
+            match result {
+                Ok(game_count) => {
+                    report.valid_blocks += 1;
+                    report.valid_games += game_count as u64;
+                    if report.errors.is_empty() {
+                        report.last_good_offset = block.offset() + block.stored_len();
+                    }
+
+                    let entry = self.index().and_then(|index| index.get(position));
+                    if entry.is_some_and(|entry| {
+                        entry.offset != block.offset() as u64
+                            || entry.game_count as usize != game_count
+                    }) {
+                        report.errors.push(ReadError::IndexMismatch {
+                            offset: block.offset(),
+                        });
+                    }
+                }
+                Err(err) => report.errors.push(err),
+            }
+        }
+
+        if let Some(err) = scan_error {
+            report.errors.push(err);
+        } else if self
+            .index()
+            .is_some_and(|index| index.len() != raw_blocks.len())
+        {
+            report.errors.push(ReadError::IndexMismatch {
+                offset: blocks.offset(),
+            });
+        } else if let Some(err) = self.header.and_then(|_| read_index(self.data()).err()) {
+            report.errors.push(err);
+        } else if self.index().is_none()
+            && blocks.at_end_marker()
+            && blocks.offset() + 4 != self.data().len()
+        {
+            report.errors.push(ReadError::TrailingData {
+                offset: blocks.offset(),
+            });
+        }
+        report
+    }
+
+    /// Iterates over every game in the archive, in order. Compressed blocks come out as a
+    /// `ReadError::CompressedBlock` each, see `games_owned`.
+    pub fn games(&self) -> impl Iterator<Item = Result<GameRef<'_>, ReadError>> {
+        self.blocks().flat_map(block_games)
+    }
+
+    /// Iterates over every game in the archive in parallel, across blocks. Games come out in no particular order.
+    /// Compressed blocks come out as a `ReadError::CompressedBlock` each, see `par_games_owned`.
+    pub fn par_games(&self) -> impl ParallelIterator<Item = Result<GameRef<'_>, ReadError>> {
+        self.par_blocks().flat_map_iter(block_games)
+    }
+
+    /// Iterates over every game in the archive, in order, decoded into owned `Game`s. Unlike `games`, this reads
+    /// compressed blocks too, at the cost of copying every game.
+    pub fn games_owned(&self) -> impl Iterator<Item = Result<Game, ReadError>> {
+        self.blocks().flat_map(block_games_owned)
+    }
+
+    /// Iterates over every game in the archive in parallel, across blocks, decoded into owned `Game`s. Games come
+    /// out in no particular order.
+    pub fn par_games_owned(&self) -> impl ParallelIterator<Item = Result<Game, ReadError>> {
+        self.par_blocks().flat_map_iter(block_games_owned)
+    }
+}
+
+/// What `ArchiveReader::verify` found when checking an archive.
+#[derive(Debug)]
+pub struct VerifyReport {
+    /// Number of blocks whose checksum and contents are valid.
+    pub valid_blocks: usize,
+    /// Number of games in the valid blocks.
+    pub valid_games: u64,
+    /// Every problem found, in archive order.
+    pub errors: Vec<ReadError>,
+    /// End of the last block before the first damaged or truncated one. Everything before this offset is intact.
+    pub last_good_offset: usize,
+}
+
+impl VerifyReport {
+    /// Whether no problems were found.
+    #[must_use]
+    pub const fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Reads the block index of an archive with a file header. Returns `None` if the archive doesn't end with a footer.
+///
+/// Besides decoding, the entries have to be consistent: offsets within the blocks and increasing, and game numbers
+/// starting at 0 and adding up, since lookups by game binary search them.
+pub(crate) fn read_index(data: &[u8]) -> Result<Option<Vec<BlockIndexEntry>>, ReadError> {
+    if data.len() < HEADER_LEN + FOOTER_LEN || !data.ends_with(&INDEX_MAGIC) {
+        return Ok(None);
+    }
+    let footer = &data[data.len() - FOOTER_LEN..];
+    let offset = u64::from_le_bytes(footer[..8].try_into().unwrap_or_default());
+    let invalid = |source| ReadError::InvalidIndex { offset, source };
+
+    // The index is length-prefixed like a block, and has to end right where the footer starts.
+    let index_start = usize::try_from(offset)
+        .ok()
+        .filter(|start| (HEADER_LEN + 4..=data.len() - FOOTER_LEN - 4).contains(start))
+        .ok_or_else(|| invalid(None))?;
+    let index_data = &data[index_start + 4..data.len() - FOOTER_LEN];
+    let length = u32::from_le_bytes(
+        data[index_start..index_start + 4]
+            .try_into()
+            .unwrap_or_default(),
+    );
+    if length as usize != index_data.len() {
+        return Err(invalid(None));
+    }
+
+    let index = BlockIndexRef::read_as_root(index_data).map_err(|source| invalid(Some(source)))?;
+    let blocks_end = index_start - 4;
+    let mut entries: Vec<BlockIndexEntry> = Vec::new();
+    for entry in index.blocks().map_err(|source| invalid(Some(source)))? {
+        let entry = BlockIndexEntry::from(entry);
+        let (min_offset, first_game) = entries.last().map_or((HEADER_LEN as u64, 0), |previous| {
+            (
+                previous.offset + 1,
+                previous.first_game + u64::from(previous.game_count),
+            )
+        });
+        if entry.offset < min_offset
+            || entry.offset >= blocks_end as u64
+            || entry.first_game != first_game
+        {
+            return Err(invalid(None));
+        }
+        entries.push(entry);
+    }
+    Ok(Some(entries))
+}
+
+/// How the blocks of an archive are stored, as given by its file header.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockFormat<'a> {
+    /// The archive's header flags, which say what's stored after each block's length. 0 for archives without a
+    /// header.
+    pub flags: u32,
+    /// The zstd dictionary stored after the file header, if there is one.
+    pub dictionary: Option<&'a [u8]>,
+}
+
+/// Reads the length-prefixed block at the given offset. A zero length (the end of the blocks) is returned as an
+/// empty block.
+pub(crate) fn read_block<'a>(
+    data: &'a [u8],
+    offset: usize,
+    format: BlockFormat<'a>,
+) -> Result<RawBlock<'a>, ReadError> {
+    let flags = format.flags;
+    let mut remaining = data.get(offset..).unwrap_or_default();
+    let mut read_u32 = || {
+        let (value, rest) = remaining.split_first_chunk::<4>()?;
+        remaining = rest;
+        Some(u32::from_le_bytes(*value))
+    };
+
+    // Read the 4-byte block length (little-endian u32)
+    let Some(length) = read_u32() else {
+        return Err(ReadError::TruncatedLength { offset });
+    };
+    let length = length as usize;
+    if length == 0 {
+        return Ok(RawBlock {
+            offset,
+            prefix_len: 4,
+            data: &[],
+            checksum: None,
+            encoding: ENCODING_NONE,
+            dictionary: None,
+        });
+    }
+
+    // Then the 4-byte checksum and encoding, if the archive has them
+    let checksum = if flags & FLAG_BLOCK_CHECKSUMS == 0 {
+        None
+    } else {
+        Some(read_u32().ok_or(ReadError::TruncatedLength { offset })?)
+    };
+    let encoding = if flags & FLAG_BLOCK_ENCODINGS == 0 {
+        ENCODING_NONE
+    } else {
+        read_u32().ok_or(ReadError::TruncatedLength { offset })?
+    };
+    let known = match encoding {
+        ENCODING_NONE | ENCODING_ZSTD => true,
+        ENCODING_ZSTD_DICTIONARY => format.dictionary.is_some(),
+        _ => false,
+    };
+    if !known {
+        return Err(ReadError::UnsupportedEncoding { offset, encoding });
+    }
+    let prefix_len = data.len() - offset - remaining.len();
+
+    // Check if we have enough bytes for the block data
+    let Some(block_data) = remaining.get(..length) else {
+        return Err(ReadError::TruncatedBlock {
+            offset,
+            length,
+            available: remaining.len(),
+        });
+    };
+
+    Ok(RawBlock {
+        offset,
+        prefix_len,
+        data: block_data,
+        checksum,
+        encoding,
+        dictionary: format.dictionary,
+    })
+}
+
+/// Flattens a block (or the error reading it) into its games.
+fn block_games(
+    block: Result<RawBlock<'_>, ReadError>,
+) -> impl Iterator<Item = Result<GameRef<'_>, ReadError>> {
+    match block.and_then(|block| block.games()) {
+        Ok(games) => Either::Left(games),
+        Err(err) => Either::Right(iter::once(Err(err))),
+    }
+}
+
+/// Decodes a block (or passes on the error reading it) into owned games.
+fn block_games_owned(block: Result<RawBlock<'_>, ReadError>) -> Vec<Result<Game, ReadError>> {
+    let block = match block.and_then(|block| block.decode()) {
+        Ok(block) => block,
+        Err(err) => return vec![Err(err)],
+    };
+    match block.games() {
+        Ok(games) => games
+            .map(|game| {
+                game.and_then(|game| Game::try_from(game).map_err(|source| block.invalid(source)))
+            })
+            .collect(),
+        Err(err) => vec![Err(err)],
+    }
+}
+
+/// A single block of an archive, as stored. The data may still need to be decompressed.
+#[derive(Debug, Clone, Copy)]
+pub struct RawBlock<'a> {
+    offset: usize,
+    prefix_len: usize,
+    data: &'a [u8],
+    checksum: Option<u32>,
+    encoding: u32,
+    dictionary: Option<&'a [u8]>,
+}
+
+impl<'a> RawBlock<'a> {
+    /// Gets the byte offset of the block's length prefix in the archive.
+    #[must_use]
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Gets the block's data as stored, without its length prefix. Use `decode` to get the `Block` itself.
+    #[must_use]
+    pub const fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Gets the CRC32 checksum stored for the block, if the archive has checksums.
+    #[must_use]
+    pub const fn checksum(&self) -> Option<u32> {
+        self.checksum
+    }
+
+    /// Gets the block's encoding, one of the `ENCODING_` constants in `header`.
+    #[must_use]
+    pub const fn encoding(&self) -> u32 {
+        self.encoding
+    }
+
+    /// Gets the size of the block in the archive, including its length prefix, checksum and encoding.
+    #[must_use]
+    pub const fn stored_len(&self) -> usize {
+        self.prefix_len + self.data.len()
+    }
+
+    /// Checks the block's data against its stored checksum. Blocks without a checksum always pass.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the checksum doesn't match.
+    pub fn check_checksum(&self) -> Result<(), ReadError> {
+        let Some(expected) = self.checksum else {
+            return Ok(());
+        };
+        let actual = crc32fast::hash(self.data);
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(ReadError::ChecksumMismatch {
+                offset: self.offset,
+                expected,
+                actual,
+            })
+        }
+    }
+
+    /// Fully verifies the block: checks its checksum, decompresses it, then decodes every table in it. Returns the
+    /// number of games in the block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the checksum doesn't match, or if any part of the block isn't valid.
+    pub fn verify(&self) -> Result<usize, ReadError> {
+        self.check_checksum()?;
+        self.decode()?.verify()
+    }
+
+    /// Decompresses the block if needed. Uncompressed blocks are borrowed straight from the archive.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block can't be decompressed.
+    pub fn decode(&self) -> Result<DecodedBlock<'a>, ReadError> {
+        let decompressed = match (self.encoding, self.dictionary) {
+            (ENCODING_ZSTD, _) => zstd::decode_all(self.data),
+            (ENCODING_ZSTD_DICTIONARY, Some(dictionary)) => zstd::stream::Decoder::with_dictionary(
+                self.data, dictionary,
+            )
+            .and_then(|mut decoder| {
+                let mut data = Vec::new();
+                decoder.read_to_end(&mut data)?;
+                Ok(data)
+            }),
+            _ => {
+                return Ok(DecodedBlock {
+                    offset: self.offset,
+                    data: Cow::Borrowed(self.data),
+                });
+            }
+        };
+        let data = decompressed.map_err(|source| ReadError::Decompression {
+            offset: self.offset,
+            source,
+        })?;
+        Ok(DecodedBlock {
+            offset: self.offset,
+            data: Cow::Owned(data),
+        })
+    }
+
+    /// Gets the number of games in the block. Compressed blocks have to be decompressed for this.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block can't be decompressed or isn't a valid `Block`.
+    pub fn game_count(&self) -> Result<usize, ReadError> {
+        self.decode()?.game_count()
+    }
+
+    /// Iterates over the games in the block, straight from the archive.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block is compressed (see `decode`) or isn't a valid `Block`. Games that fail to
+    /// decode are returned as errors by the iterator.
+    pub fn games(&self) -> Result<BlockGames<'a>, ReadError> {
+        Ok(BlockGames {
+            offset: self.offset,
+            games: self.game_vector()?.iter(),
+        })
+    }
+
+    /// Gets the nth game in the block, straight from the archive, or `None` if the block has fewer games. Games in
+    /// compressed blocks come back as `ReadError::CompressedBlock`.
+    #[must_use]
+    pub fn game(&self, n: usize) -> Option<Result<GameRef<'a>, ReadError>> {
+        let games = match self.game_vector() {
+            Ok(games) => games,
+            Err(err) => return Some(Err(err)),
+        };
+        games.get(n).map(|game| {
+            game.map_err(|source| ReadError::InvalidBlock {
+                offset: self.offset,
+                source,
+            })
+        })
+    }
+
+    fn game_vector(
+        &self,
+    ) -> Result<planus::Vector<'a, Result<GameRef<'a>, planus::Error>>, ReadError> {
+        if self.encoding != ENCODING_NONE {
+            return Err(ReadError::CompressedBlock {
+                offset: self.offset,
+            });
+        }
+        game_vector(self.data, self.offset)
+    }
+}
+
+/// A block that's ready to be read: either borrowed from the archive or decompressed into memory.
+#[derive(Debug, Clone)]
+pub struct DecodedBlock<'a> {
+    offset: usize,
+    data: Cow<'a, [u8]>,
+}
+
+impl DecodedBlock<'_> {
+    /// Gets the byte offset of the block's length prefix in the archive.
+    #[must_use]
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Gets the block's `Block` `FlatBuffer`.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Gets the block's root table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block isn't a valid `Block`.
+    pub fn block(&self) -> Result<BlockRef<'_>, ReadError> {
+        read_root(&self.data, self.offset)
+    }
+
+    /// Decodes every table in the block (unlike `block`, which only checks what's accessed). Returns the number of
+    /// games in the block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any part of the block isn't valid.
+    pub fn verify(&self) -> Result<usize, ReadError> {
+        let block = Block::try_from(self.block()?).map_err(|source| self.invalid(source))?;
+        let ArchiveType::Archive(archive) = block.archive;
+        Ok(archive.games.len())
+    }
+
+    /// Gets the number of games in the block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block isn't a valid `Block`.
+    pub fn game_count(&self) -> Result<usize, ReadError> {
+        Ok(self.game_vector()?.len())
+    }
+
+    /// Iterates over the games in the block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block isn't a valid `Block`. Games that fail to decode are returned as errors by
+    /// the iterator.
+    pub fn games(&self) -> Result<BlockGames<'_>, ReadError> {
+        Ok(BlockGames {
+            offset: self.offset,
+            games: self.game_vector()?.iter(),
+        })
+    }
+
+    /// Gets the nth game in the block, or `None` if the block has fewer games.
+    #[must_use]
+    pub fn game(&self, n: usize) -> Option<Result<GameRef<'_>, ReadError>> {
+        let games = match self.game_vector() {
+            Ok(games) => games,
+            Err(err) => return Some(Err(err)),
+        };
+        games
+            .get(n)
+            .map(|game| game.map_err(|source| self.invalid(source)))
+    }
+
+    fn game_vector(
+        &self,
+    ) -> Result<planus::Vector<'_, Result<GameRef<'_>, planus::Error>>, ReadError> {
+        game_vector(&self.data, self.offset)
+    }
+
+    const fn invalid(&self, source: planus::Error) -> ReadError {
+        ReadError::InvalidBlock {
+            offset: self.offset,
+            source,
+        }
+    }
+}
+
+/// Reads the root table of a block's `Block` `FlatBuffer`. The offset is the block's, for errors.
+fn read_root(data: &[u8], offset: usize) -> Result<BlockRef<'_>, ReadError> {
+    BlockRef::read_as_root(data).map_err(|source| ReadError::InvalidBlock { offset, source })
+}
+
+/// Gets the games of a block's `Block` `FlatBuffer`. The offset is the block's, for errors.
+fn game_vector(
+    data: &[u8],
+    offset: usize,
+) -> Result<planus::Vector<'_, Result<GameRef<'_>, planus::Error>>, ReadError> {
+    let invalid = |source| ReadError::InvalidBlock { offset, source };
+    let ArchiveTypeRef::Archive(archive) = read_root(data, offset)?.archive().map_err(invalid)?;
+    archive.games().map_err(invalid)
+}
+
+/// Iterator over the games of a single block.
+pub struct BlockGames<'a> {
+    offset: usize,
+    games: planus::vectors::Iter<'a, Result<GameRef<'a>, planus::Error>>,
+}
+
+impl<'a> Iterator for BlockGames<'a> {
+    type Item = Result<GameRef<'a>, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        self.games
+            .next()
+            .map(|game| game.map_err(|source| ReadError::InvalidBlock { offset, source }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.games.size_hint()
+    }
+}
+
+/// Iterator over the length-prefixed blocks of an archive.
+///
+/// Stops at a zero block length, which marks the end of the blocks in an archive with a block index. Also stops
+/// after returning an error, since there's no way to find the next block after a truncated one.
+pub struct BlockIterator<'a> {
+    data: &'a [u8],
+    offset: usize,
+    format: BlockFormat<'a>,
+    failed: bool,
+}
+
+impl<'a> BlockIterator<'a> {
+    /// Creates an iterator over the blocks in the given data, which must start with a block rather than a file
+    /// header, and store nothing but the length in front of each block (like archives written before the header
+    /// existed).
+    #[must_use]
+    pub const fn new(data: &'a [u8]) -> Self {
+        Self::starting_at(
+            data,
+            0,
+            BlockFormat {
+                flags: 0,
+                dictionary: None,
+            },
+        )
+    }
+
+    /// Creates an iterator over the blocks in the given archive data, starting at the given offset.
+    #[must_use]
+    pub const fn starting_at(data: &'a [u8], offset: usize, format: BlockFormat<'a>) -> Self {
+        Self {
+            data,
+            offset,
+            format,
+            failed: false,
+        }
+    }
+
+    /// Whether the iterator stopped at the marker after the last block, rather than at the end of the data or an
+    /// error.
+    #[must_use]
+    pub fn at_end_marker(&self) -> bool {
+        !self.failed && self.data[self.offset..].starts_with(&[0; 4])
+    }
+
+    /// Gets the byte offset where the next block is expected to start. Once iteration has finished without an
+    /// error, this is the end of the last complete block.
+    #[must_use]
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for BlockIterator<'a> {
+    type Item = Result<RawBlock<'a>, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.offset == self.data.len() {
+            return None;
+        }
+
+        let block = read_block(self.data, self.offset, self.format);
+        match &block {
+            Ok(block) if block.data.is_empty() => return None,
+            Ok(block) => self.offset += block.stored_len(),
+            Err(_) => self.failed = true,
+        }
+        Some(block)
+    }
+}
@@ -0,0 +1,160 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::{
+    header::{FOOTER_LEN, FileHeader, HEADER_LEN, INDEX_MAGIC},
+    reader::{BlockFormat, RawBlock, ReadError, read_block, read_index},
+    serializer::Serializer,
+};
+
+/// A stretch of an archive that was dropped because it couldn't be read.
+#[derive(Debug)]
+pub struct DamagedRegion {
+    /// Byte offset where the damage starts.
+    pub offset: usize,
+    /// Number of bytes dropped, up to the next readable block (or the end of the blocks).
+    pub length: usize,
+    /// What was wrong with the data at `offset`.
+    pub error: ReadError,
+}
+
+/// What `repair` salvaged from an archive.
+#[derive(Debug)]
+pub struct RepairReport {
+    /// Number of blocks copied to the repaired archive.
+    pub kept_blocks: usize,
+    /// Number of games in the blocks that were kept.
+    pub kept_games: u64,
+    /// Every region that was dropped, in archive order.
+    pub damaged: Vec<DamagedRegion>,
+}
+
+impl RepairReport {
+    /// Gets the total number of bytes dropped.
+    #[must_use]
+    pub fn bytes_lost(&self) -> usize {
+        self.damaged.iter().map(|region| region.length).sum()
+    }
+}
+
+/// Salvages every intact block of a damaged archive into a new one.
+///
+/// Blocks are found by walking the length prefixes, like `ArchiveReader::blocks`, and damage doesn't end the walk.
+/// Every block that passes `RawBlock::verify` is copied over, compressed the way the serializer is set up to
+/// compress blocks. A corrupted block whose length prefix is intact (another block follows right after it) is
+/// skipped as a whole. Otherwise the walk picks up at the next block listed in the old block index that verifies,
+/// and only if there's none (or no index), the data is searched byte by byte for the next block that verifies.
+/// Everything in between is dropped. The new archive is finished with a fresh block index (and gets checksums, even
+/// if the old one didn't have them).
+///
+/// # Errors
+///
+/// Returns an error if the archive's file header isn't supported, or if writing the new archive fails.
+pub fn repair<W: Write>(data: &[u8], serializer: &mut Serializer<W>) -> Result<RepairReport> {
+    let header = FileHeader::read(data)?;
+    let dictionary = match header {
+        Some(header) => header.read_dictionary(data)?,
+        None => None,
+    };
+    let format = BlockFormat {
+        flags: header.map_or(0, |header| header.flags),
+        dictionary,
+    };
+    let mut offset = match (header, dictionary) {
+        (_, Some(dictionary)) => HEADER_LEN + 4 + dictionary.len(),
+        (Some(_), None) => HEADER_LEN,
+        (None, None) => 0,
+    };
+    let end = blocks_end(data, offset);
+    // The old index is only used to find blocks again after damage, so a damaged one just isn't any help.
+    let indexed: Vec<usize> = read_index(data)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|entry| usize::try_from(entry.offset).ok())
+        .collect();
+
+    let mut report = RepairReport {
+        kept_blocks: 0,
+        kept_games: 0,
+        damaged: vec![],
+    };
+    while offset < end {
+        match salvage_block(&data[..end], offset, format) {
+            Ok((block, game_count)) => {
+                serializer.add_encoded_block(block.decode()?.data(), game_count)?;
+                report.kept_blocks += 1;
+                report.kept_games += game_count as u64;
+                offset += block.stored_len();
+            }
+            Err(error) => {
+                let data = &data[..end];
+                let next = skip_block(data, offset, format)
+                    .or_else(|| {
+                        indexed.iter().copied().find(|&next| {
+                            next > offset && salvage_block(data, next, format).is_ok()
+                        })
+                    })
+                    .unwrap_or_else(|| {
+                        (offset + 1..end)
+                            .find(|&next| salvage_block(data, next, format).is_ok())
+                            .unwrap_or(end)
+                    });
+                report.damaged.push(DamagedRegion {
+                    offset,
+                    length: next - offset,
+                    error,
+                });
+                offset = next;
+            }
+        }
+    }
+
+    serializer.finish()?;
+    Ok(report)
+}
+
+/// Reads and fully verifies the block at the given offset, returning it with its game count.
+fn salvage_block<'a>(
+    data: &'a [u8],
+    offset: usize,
+    format: BlockFormat<'a>,
+) -> Result<(RawBlock<'a>, usize), ReadError> {
+    let block = read_block(data, offset, format)?;
+    if block.data().is_empty() {
+        // A zero length is only valid right before the block index.
+        return Err(ReadError::TrailingData { offset });
+    }
+    let game_count = block.verify()?;
+    Ok((block, game_count))
+}
+
+/// Skips over a damaged block whose length prefix can still be read, returning where the next block starts. The
+/// length is only trusted if it leads to another readable length prefix, or to the end of the blocks.
+fn skip_block(data: &[u8], offset: usize, format: BlockFormat<'_>) -> Option<usize> {
+    let block = read_block(data, offset, format).ok()?;
+    if block.data().is_empty() {
+        return None;
+    }
+    let next = offset + block.stored_len();
+    (next == data.len() || read_block(data, next, format).is_ok()).then_some(next)
+}
+
+/// Finds where the blocks end: at the end-of-blocks marker if the archive still has a footer that points at an
+/// index right after it, otherwise at the end of the data.
+fn blocks_end(data: &[u8], blocks_offset: usize) -> usize {
+    if data.len() < blocks_offset + 4 + FOOTER_LEN || !data.ends_with(&INDEX_MAGIC) {
+        return data.len();
+    }
+    let footer = &data[data.len() - FOOTER_LEN..];
+    let index_offset = u64::from_le_bytes(footer[..8].try_into().unwrap_or_default());
+    usize::try_from(index_offset)
+        .ok()
+        .filter(|&index_offset| {
+            (blocks_offset + 4..data.len() - FOOTER_LEN).contains(&index_offset)
+                && data[index_offset - 4..index_offset] == [0; 4]
+        })
+        .map_or(data.len(), |index_offset| index_offset - 4)
+}
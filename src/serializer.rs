@@ -1,30 +1,105 @@
-use std::{collections::HashMap, io::Write};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use planus::{Builder, Offset, WriteAsOffset};
 
-use crate::generated_chess::{Archive, ArchiveType, Block, Game, Move};
+use crate::{
+    append::AppendPoint,
+    generated_chess::{
+        Archive, ArchiveType, Block, BlockIndex, BlockIndexEntry, Game, GameInfo, GameInfoRef,
+        GameRef, Move,
+    },
+    header::{
+        ENCODING_NONE, ENCODING_ZSTD, ENCODING_ZSTD_DICTIONARY, FLAG_DICTIONARY, FOOTER_LEN,
+        FileHeader, INDEX_MAGIC,
+    },
+};
 
 const MAX_GAMES_PER_BLOCK: usize = 500_000;
 
+/// Blocks are always finished once they reach this many bytes, whatever the block size settings say. That keeps
+/// them well clear of the 2 GiB a `FlatBuffer` can address (and the 4 GiB a `u32` length prefix can describe),
+/// even with one more large game on top.
+const MAX_BLOCK_BYTES: usize = 1 << 30;
+
+/// Size of the samples a block is cut into when training a dictionary on it.
+const DICTIONARY_SAMPLE_LEN: usize = 256;
+
+/// Statistics about a single block written by the serializer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStats {
+    /// Number of games in the block.
+    pub games: usize,
+    /// Number of distinct moves stored in the block after deduplication. Zero for blocks added with
+    /// `Serializer::add_encoded_block`.
+    pub unique_moves: usize,
+    /// Size of the block in the output, including its length prefix, checksum and encoding.
+    pub bytes: u64,
+}
+
+/// When a sharded serializer moves on to a new archive, see `Serializer::set_shards`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardLimit {
+    /// Start a new archive once the current one holds this many games.
+    Games(u64),
+    /// Start a new archive once this many bytes have been written to the current one.
+    Bytes(u64),
+}
+
+/// Called with the writer of a finished archive, to get the writer for the next one.
+type NextWriter<T> = Box<dyn FnMut(&mut T) -> Result<T> + Send>;
+
+/// Sharding settings, along with where the current archive stands.
+struct Shards<T> {
+    limit: ShardLimit,
+    next_writer: NextWriter<T>,
+    /// Set once the current archive has reached the limit. The next block then starts a new archive, so no empty
+    /// archive is left behind when the input ends right at the limit.
+    full: bool,
+}
+
+/// A block built by a serializer from `Serializer::collecting`, ready to be added to another serializer.
+pub(crate) struct BuiltBlock {
+    data: Vec<u8>,
+    games: usize,
+    unique_moves: usize,
+}
+
 /// A serializer for the chess binary protocol.
 ///
 /// Wraps the `planus::Builder` API with something nicer that also writes more efficiently.
-/// Moves are deduplicated per block by default, resulting in smaller archives.
+/// Moves and strings are deduplicated per block by default, resulting in smaller archives.
 ///
 /// The serializer writes games in chunks called blocks. `FlatBuffer` serialization occurs in memory,
 /// so it's important to flush this regularly using chunking logic. The serializer does this by maintaining
-/// a list of added games. Once the amount of added games exceeds the `max_games_per_block` setting,
-/// the serializer will end the current block and start a new one.
+/// a list of added games. Once the amount of added games exceeds the `max_games_per_block` setting, or the block
+/// has grown past the `max_block_bytes` setting (whichever comes first), the serializer will end the current block
+/// and start a new one. A block that still ends up too large for its `u32` length prefix is refused with an error
+/// rather than written.
 ///
-/// The output format is a sequence of the following:
+/// The output starts with a `FileHeader` (magic bytes, format version and flags), written before the first
+/// block. It's followed by a sequence of the following:
 ///
+/// ```text
+/// | u32 uint block length | u32 CRC32 of block data | u32 encoding | block data |
 /// ```
-/// | u32 uint block length | block data |
-/// ```
 ///
-/// Decoding occurs by first parsing the 32-bit block length, then reading the following block data. Repeat
-/// until the end of the archive is reached.
+/// Blocks are stored as-is by default. With `set_compression_level`, each block is compressed with zstd, unless
+/// that doesn't make it any smaller. The encoding says which it is. With `set_dictionary_size` as well, a zstd
+/// dictionary is trained on the first block and stored once after the header, and every block is compressed
+/// against it. That way blocks still decompress independently, but the strings and moves every block repeats
+/// don't have to be stored in full each time.
+///
+/// Decoding occurs by first checking the header, then parsing the 32-bit block length, checksum and encoding and
+/// reading (and if needed, decompressing) the following block data. Repeat until a block length of 0 or the end of
+/// the archive is reached. `finish` ends the archive with a `BlockIndex` of every block, so readers can also seek
+/// straight to a block (see `FileHeader` for the layout).
+///
+/// With `set_shards`, the output is split into several complete archives, each with its own header, dictionary
+/// and index. A new archive is started between blocks once the current one reaches a number of games or bytes.
 ///
 /// Note that because `FlatBuffer` uses 32-bit pointers, the maximum size of a block is 32-bit. Hence the block
 /// length `u32`.
@@ -32,14 +107,30 @@ pub struct Serializer<T: Write> {
     writer: T,
     builder: Builder,
     move_map: HashMap<Move, Offset<Move>>,
+    string_map: HashMap<String, Offset<str>>,
     games_list: Vec<Offset<Game>>,
     max_games_per_block: usize,
+    max_block_bytes: usize,
+    blocks: Vec<BlockStats>,
+    bytes_written: u64,
+    /// Size of the current archive so far, which block offsets count from. Differs from `bytes_written` once the
+    /// output is sharded, or when appending to an existing archive.
+    archive_len: u64,
+    header_written: bool,
+    index: Vec<BlockIndexEntry>,
+    games_written: u64,
+    finished: bool,
+    compression_level: Option<i32>,
+    dictionary_size: Option<usize>,
+    dictionary: Option<Vec<u8>>,
+    collected: Option<Vec<BuiltBlock>>,
+    shards: Option<Shards<T>>,
 }
 
 impl<T: Write> Serializer<T> {
     /// Creates a new serializer with the given writer.
     ///
-    /// By default, the maximum number of games per block is set to 500,000.
+    /// By default, the maximum number of games per block is set to 500,000, and the maximum block size to 1 GiB.
     pub fn new(writer: T) -> Self {
         let builder = Builder::new();
         let move_map = HashMap::new();
@@ -47,16 +138,87 @@ impl<T: Write> Serializer<T> {
             writer,
             builder,
             move_map,
+            string_map: HashMap::new(),
             games_list: vec![],
             max_games_per_block: MAX_GAMES_PER_BLOCK,
+            max_block_bytes: MAX_BLOCK_BYTES,
+            blocks: vec![],
+            bytes_written: 0,
+            archive_len: 0,
+            header_written: false,
+            index: vec![],
+            games_written: 0,
+            finished: false,
+            compression_level: None,
+            dictionary_size: None,
+            dictionary: None,
+            collected: None,
+            shards: None,
         }
     }
 
+    /// Creates a serializer that adds blocks to an existing archive, found with `AppendPoint::find`, instead of
+    /// starting a new one. The writer has to be positioned at the append point, like an `AppendWriter` is.
+    ///
+    /// The header isn't written again and no dictionary is trained: with `set_compression_level`, blocks are
+    /// compressed against the archive's own dictionary, if it has one. `finish` writes a block index covering both the
+    /// old blocks and the new ones.
+    pub fn append(writer: T, point: AppendPoint) -> Self {
+        let mut serializer = Self::new(writer);
+        serializer.header_written = true;
+        serializer.archive_len = point.offset;
+        serializer.games_written = point.game_count();
+        serializer.dictionary = point.dictionary;
+        serializer.index = point.index;
+        serializer
+    }
+
     /// Allows setting the maximum number of games per block.
     pub const fn set_max_games_per_block(&mut self, max_games_per_block: usize) {
         self.max_games_per_block = max_games_per_block;
     }
 
+    /// Allows setting the size in bytes a block is finished at, before compression. Blocks end up slightly larger
+    /// than this, since the game that crosses the limit is kept in the block. Limits above 1 GiB are lowered to
+    /// 1 GiB.
+    pub fn set_max_block_bytes(&mut self, max_block_bytes: usize) {
+        self.max_block_bytes = max_block_bytes.min(MAX_BLOCK_BYTES);
+    }
+
+    /// Sets the zstd level blocks are compressed with, or `None` (the default) to store them uncompressed.
+    pub const fn set_compression_level(&mut self, compression_level: Option<i32>) {
+        self.compression_level = compression_level;
+    }
+
+    /// Sets the maximum size of the zstd dictionary trained on the first block, or `None` (the default) to
+    /// compress blocks without one. Only used if blocks are compressed, and has to be set before the first block is
+    /// written.
+    ///
+    /// If training fails, usually because the first block is too small to learn anything from, blocks are
+    /// compressed without a dictionary.
+    pub const fn set_dictionary_size(&mut self, dictionary_size: Option<usize>) {
+        self.dictionary_size = dictionary_size;
+    }
+
+    /// Splits the output into several archives, starting a new one once the current archive reaches `limit`. To
+    /// start it, `next_writer` is called with the writer of the finished archive (to sync it to disk, for example)
+    /// and returns the writer for the next one. Has to be set before the first block is written.
+    ///
+    /// Archives only ever end between blocks. With a game limit, the block is finished early so the archive ends
+    /// right at the limit, except for blocks added whole with `add_encoded_block`. With a byte limit, archives end
+    /// with the block that crosses the limit, so they're only as close to it as the block size allows.
+    pub fn set_shards(
+        &mut self,
+        limit: ShardLimit,
+        next_writer: impl FnMut(&mut T) -> Result<T> + Send + 'static,
+    ) {
+        self.shards = Some(Shards {
+            limit,
+            next_writer: Box::new(next_writer),
+            full: false,
+        });
+    }
+
     /// Adds a move to the serializer, returning the Planus offset.
     /// Deduplicates moves by default so that they are only serialized once.
     /// You can safely call this method multiple times with the same move and it will return the same offset.
@@ -68,28 +230,199 @@ impl<T: Write> Serializer<T> {
         })
     }
 
+    /// Adds a string to the serializer, returning the Planus offset.
+    /// Like moves, strings are deduplicated per block, which matters a lot for things like player names and events
+    /// that repeat across many games.
+    pub fn add_string(&mut self, value: &str) -> Offset<str> {
+        if let Some(offset) = self.string_map.get(value) {
+            return *offset;
+        }
+        let offset = value.prepare(&mut self.builder);
+        self.string_map.insert(value.to_owned(), offset);
+        offset
+    }
+
+    /// Adds a game's info table to the serializer, returning the Planus offset.
+    /// The offset should be passed to the game that owns it before that game is added.
+    pub fn add_game_info<R: WriteAsOffset<GameInfo>>(&mut self, info: &R) -> Offset<GameInfo> {
+        info.prepare(&mut self.builder)
+    }
+
     /// Adds a game to the serializer, returning the Planus offset.
-    /// If the game count is greater than or equal to the maximum games per block,
-    /// will finish serializing the current block and start a new one. Hence the Result type.
+    /// If the game count is greater than or equal to the maximum games per block, or the block has reached the
+    /// maximum block size, will finish serializing the current block and start a new one. Hence the Result type.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the current block had to be written and writing failed (including when it's too large
+    /// for its length prefix), or if the serializer has already been finished.
     pub fn add_game<R: WriteAsOffset<Game>>(&mut self, game: &R) -> Result<Offset<Game>> {
+        if self.finished {
+            bail!("Can't add games after the archive has been finished");
+        }
         let offset = game.prepare(&mut self.builder);
         self.games_list.push(offset);
-        if self.games_list.len() >= self.max_games_per_block {
+        if self.games_list.len() >= self.max_games_per_block
+            || self.builder.len() >= self.max_block_bytes
+            || self.fills_shard()
+        {
             self.finish_current_block()?;
         }
         Ok(offset)
     }
 
+    /// Re-encodes a game read from an archive into the current block, returning the Planus offset like `add_game`.
+    ///
+    /// Moves and strings go through `add_move` and `add_string`, so they're deduplicated against the rest of the
+    /// block instead of being copied the way they were stored. This is how games are moved from one archive to
+    /// another, as `merge` does.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the game can't be read, or for the same reasons as `add_game`.
+    pub fn add_game_ref(&mut self, game: &GameRef<'_>) -> Result<Offset<Game>> {
+        let moves = game
+            .moves()?
+            .iter()
+            .map(|game_move| Ok(self.add_move(&Move::try_from(game_move?)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let start_position = game.start_position()?.map(|fen| self.add_string(fen));
+        let info = match game.info()? {
+            Some(info) => Some(self.add_game_info_ref(&info)?),
+            None => None,
+        };
+        self.add_game(
+            &Game::builder()
+                .result(game.result()?)
+                .start_position(start_position)
+                .moves(&moves)
+                .info(info),
+        )
+    }
+
+    /// Re-encodes a game's info table read from an archive, deduplicating its strings.
+    fn add_game_info_ref(&mut self, info: &GameInfoRef<'_>) -> Result<Offset<GameInfo>> {
+        let info = GameInfo::builder()
+            .event(info.event()?.map(|v| self.add_string(v)))
+            .site(info.site()?.map(|v| self.add_string(v)))
+            .url(info.url()?.map(|v| self.add_string(v)))
+            .white_player(info.white_player()?.map(|v| self.add_string(v)))
+            .black_player(info.black_player()?.map(|v| self.add_string(v)))
+            .white_elo(info.white_elo()?)
+            .black_elo(info.black_elo()?);
+        Ok(self.add_game_info(&info))
+    }
+
+    /// Whether a block taken from another archive can be added as it is, rather than having its games re-encoded: no
+    /// games are waiting for the current block, and the block is as full as the ones this serializer writes without
+    /// being over its game limit.
+    pub(crate) const fn can_add_whole_block(&self, game_count: usize, data_len: usize) -> bool {
+        self.games_list.is_empty()
+            && game_count <= self.max_games_per_block
+            && (game_count == self.max_games_per_block || data_len >= self.max_block_bytes)
+    }
+
+    /// Returns statistics for every block written so far, in the order they were written.
+    pub fn block_stats(&self) -> &[BlockStats] {
+        &self.blocks
+    }
+
+    /// Returns the total number of bytes written to the output stream so far, across all archives if the output is
+    /// sharded.
+    pub const fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Whether the games added so far fill the current archive, when sharding by games.
+    const fn fills_shard(&self) -> bool {
+        let Some(Shards {
+            limit: ShardLimit::Games(limit),
+            full,
+            ..
+        }) = self.shards
+        else {
+            return false;
+        };
+        // A full archive is replaced before the current block is written, so its games don't count.
+        let archived = if full { 0 } else { self.games_written };
+        archived + self.games_list.len() as u64 >= limit
+    }
+
+    /// Finishes the current archive and starts the next one on a new writer.
+    fn start_next_shard(&mut self) -> Result<()> {
+        self.write_index()?;
+        let Some(shards) = &mut self.shards else {
+            return Ok(());
+        };
+        self.writer = (shards.next_writer)(&mut self.writer)?;
+        shards.full = false;
+
+        self.archive_len = 0;
+        self.header_written = false;
+        self.dictionary = None;
+        self.index.clear();
+        self.games_written = 0;
+        Ok(())
+    }
+
     fn reset(&mut self) {
         self.move_map.clear();
+        self.string_map.clear();
         self.games_list.clear();
         self.builder.clear();
     }
 
+    /// Writes the file header if it hasn't been written yet, training the dictionary on the first block (if
+    /// enabled) and writing it after the header.
+    fn write_header(&mut self, first_block: &[u8]) -> Result<()> {
+        if self.header_written {
+            return Ok(());
+        }
+
+        let mut header = FileHeader::CURRENT;
+        if let (Some(_), Some(size)) = (self.compression_level, self.dictionary_size)
+            && !first_block.is_empty()
+        {
+            let sample_sizes: Vec<_> = first_block
+                .chunks(DICTIONARY_SAMPLE_LEN)
+                .map(<[u8]>::len)
+                .collect();
+            self.dictionary = zstd::dict::from_continuous(first_block, &sample_sizes, size).ok();
+        }
+        if self.dictionary.is_some() {
+            header.flags |= FLAG_DICTIONARY;
+        }
+
+        let header = header.to_bytes();
+        self.writer.write_all(&header)?;
+        self.bytes_written += header.len() as u64;
+        self.archive_len += header.len() as u64;
+        if let Some(dictionary) = &self.dictionary {
+            let length = u32::try_from(dictionary.len())?;
+            self.writer.write_all(&length.to_le_bytes())?;
+            self.writer.write_all(dictionary)?;
+            self.bytes_written += 4 + u64::from(length);
+            self.archive_len += 4 + u64::from(length);
+        }
+        self.header_written = true;
+        Ok(())
+    }
+
     /// Finishes serializing the current block, writing it to the output stream.
     ///
     /// Writing is a method that could fail, hence the Result type.
+    /// If no games have been added since the last block, no block is written, though the file header is written
+    /// if it hasn't been already.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the output stream fails, or if the block is too large for its length prefix.
     pub fn finish_current_block(&mut self) -> Result<()> {
+        if self.games_list.is_empty() {
+            self.reset();
+            return self.write_header(&[]);
+        }
+
         let archive = Archive::builder()
             .games(&self.games_list)
             .prepare(&mut self.builder);
@@ -100,15 +433,187 @@ impl<T: Write> Serializer<T> {
         let block = Block::builder()
             .archive(archive_type)
             .finish(&mut self.builder);
-        let result = self.builder.finish(block, None);
+        // The block is written from the builder's buffer, so move the builder out while the block is recorded.
+        let mut builder = std::mem::take(&mut self.builder);
+        let result = builder.finish(block, None);
+        let written = if let Some(collected) = &mut self.collected {
+            collected.push(BuiltBlock {
+                data: result.to_vec(),
+                games: self.games_list.len(),
+                unique_moves: self.move_map.len(),
+            });
+            Ok(())
+        } else {
+            self.write_block(result, self.games_list.len(), self.move_map.len())
+        };
+        self.builder = builder;
+        self.reset();
+
+        written
+    }
+
+    /// Adds a block that's already encoded, such as one copied from another archive. Games added before are
+    /// written out in their own block first, so the order of games is kept.
+    ///
+    /// The data must be a valid, uncompressed `Block` holding `game_count` games. It's compressed like any other
+    /// block. Its `BlockStats` have `unique_moves` set to 0, since the moves aren't decoded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the output stream fails, if the block is too large for its length prefix, or
+    /// if the serializer has already been finished.
+    pub fn add_encoded_block(&mut self, data: &[u8], game_count: usize) -> Result<()> {
+        self.add_block(data, game_count, 0)
+    }
+
+    /// Adds a block built by a serializer from `collecting`, like `add_encoded_block` but keeping the block's
+    /// count of unique moves.
+    pub(crate) fn add_built_block(&mut self, block: &BuiltBlock) -> Result<()> {
+        self.add_block(&block.data, block.games, block.unique_moves)
+    }
 
-        #[allow(clippy::cast_possible_truncation)]
-        let length = result.len() as u32;
+    fn add_block(&mut self, data: &[u8], games: usize, unique_moves: usize) -> Result<()> {
+        if self.finished {
+            bail!("Can't add blocks after the archive has been finished");
+        }
+        if !self.games_list.is_empty() {
+            self.finish_current_block()?;
+        }
+        self.write_block(data, games, unique_moves)
+    }
+
+    /// Writes a block with its length prefix, checksum and encoding, compressing it if enabled, and records it in
+    /// the index and the block stats.
+    fn write_block(&mut self, data: &[u8], games: usize, unique_moves: usize) -> Result<()> {
+        if self.shards.as_ref().is_some_and(|shards| shards.full) {
+            self.start_next_shard()?;
+        }
+        self.write_header(data)?;
+
+        let compressed = match (self.compression_level, &self.dictionary) {
+            (Some(level), Some(dictionary)) => Some((
+                ENCODING_ZSTD_DICTIONARY,
+                zstd::bulk::Compressor::with_dictionary(level, dictionary)?.compress(data)?,
+            )),
+            (Some(level), None) => Some((ENCODING_ZSTD, zstd::bulk::compress(data, level)?)),
+            (None, _) => None,
+        };
+        let (encoding, data) = match &compressed {
+            Some((encoding, compressed)) if compressed.len() < data.len() => {
+                (*encoding, compressed.as_slice())
+            }
+            _ => (ENCODING_NONE, data),
+        };
+        let Ok(length) = u32::try_from(data.len()) else {
+            bail!(
+                "Block of {} bytes is too large for its length prefix, blocks have to be smaller than 4 GiB",
+                data.len()
+            );
+        };
 
+        self.writer.write_all(&length.to_le_bytes())?;
+        self.writer
+            .write_all(&crc32fast::hash(data).to_le_bytes())?;
+        self.writer.write_all(&encoding.to_le_bytes())?;
+        self.writer.write_all(data)?;
+
+        self.index.push(BlockIndexEntry {
+            offset: self.archive_len,
+            first_game: self.games_written,
+            game_count: u32::try_from(games)?,
+        });
+        self.games_written += games as u64;
+
+        let bytes = 12 + u64::from(length);
+        self.blocks.push(BlockStats {
+            games,
+            unique_moves,
+            bytes,
+        });
+        self.bytes_written += bytes;
+        self.archive_len += bytes;
+
+        if let Some(shards) = &mut self.shards {
+            shards.full = match shards.limit {
+                ShardLimit::Games(limit) => self.games_written >= limit,
+                ShardLimit::Bytes(limit) => self.archive_len >= limit,
+            };
+        }
+        Ok(())
+    }
+
+    /// Finishes the archive (see `finish`) and returns the output stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the output stream fails.
+    pub fn into_inner(mut self) -> Result<T> {
+        self.finish()?;
+        Ok(self.writer)
+    }
+
+    /// Finishes the archive: writes the current block, then the block index and the footer pointing at it.
+    ///
+    /// Calling this more than once does nothing. No more games can be added afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the output stream fails.
+    pub fn finish(&mut self) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finish_current_block()?;
+        self.write_index()?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Ends the blocks of the current archive, then writes the block index and the footer pointing at it.
+    fn write_index(&mut self) -> Result<()> {
+        // The builder may still hold the games of the next archive's first block, so use a separate one.
+        let mut builder = Builder::new();
+        let index = BlockIndex::builder()
+            .blocks(&self.index)
+            .finish(&mut builder);
+        let result = builder.finish(index, None);
+        let Ok(length) = u32::try_from(result.len()) else {
+            bail!(
+                "Block index of {} bytes is too large for its length prefix",
+                result.len()
+            );
+        };
+
+        // A zero length marks the end of the blocks for readers that walk the length prefixes.
+        let index_offset = self.archive_len + 4;
+        self.writer.write_all(&0u32.to_le_bytes())?;
         self.writer.write_all(&length.to_le_bytes())?;
         self.writer.write_all(result)?;
-        self.reset();
+        self.writer.write_all(&index_offset.to_le_bytes())?;
+        self.writer.write_all(&INDEX_MAGIC)?;
+        self.writer.flush()?;
 
+        let written = 8 + u64::from(length) + FOOTER_LEN as u64;
+        self.bytes_written += written;
+        self.archive_len += written;
         Ok(())
     }
 }
+
+impl Serializer<io::Sink> {
+    /// Creates a serializer that keeps the blocks it builds instead of writing them, so they can be built on one
+    /// thread and added to `like` on another (see `take_built_blocks` and `add_built_block`). Blocks are split
+    /// with the same limits as `like`.
+    pub(crate) fn collecting<W: Write>(like: &Serializer<W>) -> Self {
+        let mut serializer = Self::new(io::sink());
+        serializer.max_games_per_block = like.max_games_per_block;
+        serializer.max_block_bytes = like.max_block_bytes;
+        serializer.collected = Some(vec![]);
+        serializer
+    }
+
+    /// Takes the blocks built so far, in order. Games in the current block aren't included until it's finished.
+    pub(crate) fn take_built_blocks(&mut self) -> Vec<BuiltBlock> {
+        self.collected.take().unwrap_or_default()
+    }
+}
@@ -1,7 +1,8 @@
-use crate::generated_chess::{File, GameResult, MoveRef, Piece, Rank, Square};
+use crate::generated_chess::{CheckKind, File, GameResult, MoveRef, Piece, Rank, Square};
 use anyhow::Result;
 
 /// Converts a `shakmaty::Role` into a corresponding `Piece`.
+#[must_use]
 pub const fn role_to_piece(role: shakmaty::Role) -> Piece {
     match role {
         shakmaty::Role::King => Piece::King,
@@ -14,6 +15,7 @@ pub const fn role_to_piece(role: shakmaty::Role) -> Piece {
 }
 
 /// Converts a `Piece` into a corresponding `shakmaty::Role`.
+#[must_use]
 pub const fn piece_to_role(piece: Piece) -> shakmaty::Role {
     match piece {
         Piece::King => shakmaty::Role::King,
@@ -45,6 +47,7 @@ macro_rules! reverse_square_match {
     }
 }
 
+#[must_use]
 pub const fn shakmaty_square_to_square(s_square: shakmaty::Square) -> Square {
     // Macro to save us lines for a converter and without having to use unsafe.
     // Zero clue how shakmaty::Square is actually implemented so we're doing this.
@@ -55,6 +58,7 @@ pub const fn shakmaty_square_to_square(s_square: shakmaty::Square) -> Square {
     )
 }
 
+#[must_use]
 pub const fn outcome_to_game_result(outcome: shakmaty::Outcome) -> GameResult {
     use shakmaty::{Color, KnownOutcome, Outcome};
     match outcome {
@@ -71,6 +75,7 @@ pub const fn outcome_to_game_result(outcome: shakmaty::Outcome) -> GameResult {
     }
 }
 
+#[must_use]
 pub const fn shakmaty_file_to_file(s_file: pgn_reader::shakmaty::File) -> File {
     use pgn_reader::shakmaty;
     match s_file {
@@ -85,6 +90,7 @@ pub const fn shakmaty_file_to_file(s_file: pgn_reader::shakmaty::File) -> File {
     }
 }
 
+#[must_use]
 pub const fn shakmaty_rank_to_rank(s_rank: pgn_reader::shakmaty::Rank) -> Rank {
     use pgn_reader::shakmaty;
     match s_rank {
@@ -99,6 +105,7 @@ pub const fn shakmaty_rank_to_rank(s_rank: pgn_reader::shakmaty::Rank) -> Rank {
     }
 }
 
+#[must_use]
 pub const fn square_to_shakmaty_square(square: Square) -> shakmaty::Square {
     reverse_square_match!(
         square, A1, B1, C1, D1, E1, F1, G1, H1, A2, B2, C2, D2, E2, F2, G2, H2, A3, B3, C3, D3, E3,
@@ -107,16 +114,50 @@ pub const fn square_to_shakmaty_square(square: Square) -> shakmaty::Square {
     )
 }
 
-pub fn move_ref_to_san(move_ref: &MoveRef) -> Result<shakmaty::san::San> {
-    use shakmaty::san::San;
+/// Builds the position a game starts from, given its FEN start position (if it has one).
+/// Games without a start position begin from the standard starting position.
+///
+/// Chess960 start positions are accepted too, since Lichess exports them with a `[FEN]` tag like any other.
+///
+/// # Errors
+///
+/// Returns an error if the FEN can't be parsed or doesn't describe a legal position.
+pub fn start_position(fen: Option<&str>) -> Result<shakmaty::Chess> {
+    use shakmaty::{CastlingMode, fen::Fen};
+
+    let Some(fen) = fen else {
+        return Ok(shakmaty::Chess::default());
+    };
+
+    let fen: Fen = fen.parse()?;
+    match fen.clone().into_position(CastlingMode::Standard) {
+        Ok(position) => Ok(position),
+        Err(_) => Ok(fen.into_position(CastlingMode::Chess960)?),
+    }
+}
+
+/// Converts a `MoveRef` read from an archive back into a `shakmaty::san::SanPlus`, including any check
+/// or checkmate suffix.
+///
+/// # Errors
+///
+/// Returns an error if any of the move's fields fail to decode.
+pub fn move_ref_to_san(move_ref: &MoveRef) -> Result<shakmaty::san::SanPlus> {
+    use shakmaty::san::{San, SanPlus, Suffix};
     use shakmaty::CastlingSide;
 
+    let suffix = move_ref.check()?.map(|check| match check {
+        CheckKind::Check => Suffix::Check,
+        CheckKind::Checkmate => Suffix::Checkmate,
+    });
+
     // Handle castling first
     if let Some(castle_kind) = move_ref.castle()? {
-        return Ok(San::Castle(match castle_kind {
+        let san = San::Castle(match castle_kind {
             crate::generated_chess::CastleKind::Kingside => CastlingSide::KingSide,
             crate::generated_chess::CastleKind::Queenside => CastlingSide::QueenSide,
-        }));
+        });
+        return Ok(SanPlus { san, suffix });
     }
 
     let role = piece_to_role(move_ref.moved_piece()?);
@@ -148,12 +189,13 @@ pub fn move_ref_to_san(move_ref: &MoveRef) -> Result<shakmaty::san::San> {
         Rank::Eighth => shakmaty::Rank::Eighth,
     });
 
-    Ok(San::Normal {
+    let san = San::Normal {
         role,
         file: from_file,
         rank: from_rank,
         capture,
         to,
         promotion,
-    })
+    };
+    Ok(SanPlus { san, suffix })
 }
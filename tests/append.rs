@@ -0,0 +1,200 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chessb::{
+    append::{AppendPoint, AppendWriter},
+    converter::Converter,
+    reader::ArchiveReader,
+    serializer::Serializer,
+};
+
+mod common;
+
+use common::{games, sample_archive};
+
+/// Writes an archive to a file of its own in the temporary directory.
+fn temp_archive(name: &str, data: &[u8]) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("chessb-append-{}-{name}.cbin", std::process::id()));
+    fs::write(&path, data).unwrap();
+    path
+}
+
+/// Appends the games of `games.pgn` to the archive at `path`, committing them if `commit` is set.
+fn append(
+    path: &Path,
+    setup: impl FnOnce(&mut Serializer<AppendWriter>),
+    commit: bool,
+) -> AppendPoint {
+    let pgn = fs::read("games.pgn").unwrap();
+    let (writer, point) = AppendWriter::open(path).unwrap();
+    let mut serializer = Serializer::append(writer, point.clone());
+    serializer.set_max_games_per_block(20);
+    setup(&mut serializer);
+    let mut converter = Converter::new(pgn.as_slice(), serializer);
+    while converter.next_game().unwrap() {}
+    let (mut writer, _) = converter.finish().unwrap();
+    if commit {
+        writer.commit().unwrap();
+    }
+    point
+}
+
+/// Checks that an archive verifies, and that its index matches its blocks and covers `game_count` games.
+fn check_archive(archive: &[u8], game_count: u64) {
+    let reader = ArchiveReader::from_bytes(archive).unwrap();
+    assert!(reader.verify().is_ok());
+    assert_eq!(reader.game_count(), Some(game_count));
+    let offsets: Vec<_> = reader
+        .blocks()
+        .map(|block| block.unwrap().offset() as u64)
+        .collect();
+    let indexed: Vec<_> = reader
+        .index()
+        .unwrap()
+        .iter()
+        .map(|entry| entry.offset)
+        .collect();
+    assert_eq!(offsets, indexed);
+}
+
+#[test]
+fn appended_games_follow_the_old_ones() {
+    let archive = sample_archive(|_| {});
+    let path = temp_archive("follow", &archive);
+
+    let point = append(&path, |_| {}, true);
+    let appended = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(point.game_count(), 50);
+    assert_eq!(point.block_count(), 7);
+    assert_eq!(point.dropped_bytes(), 0);
+    check_archive(&appended, 100);
+    let mut expected = games(&archive);
+    expected.extend(games(&archive));
+    assert_eq!(games(&appended), expected);
+}
+
+#[test]
+fn appending_no_games_keeps_the_archive_readable() {
+    let archive = sample_archive(|_| {});
+    let path = temp_archive("empty", &archive);
+
+    let (writer, point) = AppendWriter::open(&path).unwrap();
+    let converter = Converter::new(&b""[..], Serializer::append(writer, point));
+    let (mut writer, stats) = converter.finish().unwrap();
+    writer.commit().unwrap();
+    drop(writer);
+    let appended = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(stats.games_written, 0);
+    assert_eq!(appended.len(), archive.len());
+    check_archive(&appended, 50);
+    assert_eq!(games(&appended), games(&archive));
+}
+
+#[test]
+fn a_missing_index_is_rebuilt() {
+    let archive = sample_archive(|_| {});
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    let last = reader.index().unwrap().last().unwrap();
+    let end =
+        usize::try_from(last.offset).unwrap() + reader.block(6).unwrap().unwrap().stored_len();
+    // Keep the zero length ending the blocks, but not the index after it.
+    let path = temp_archive("rebuilt", &archive[..end + 4]);
+
+    let point = append(&path, |_| {}, true);
+    let appended = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(point.game_count(), 50);
+    assert_eq!(point.offset(), end as u64);
+    check_archive(&appended, 100);
+}
+
+#[test]
+fn an_unfinished_block_at_the_end_is_dropped() {
+    let archive = sample_archive(|_| {});
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    let last = usize::try_from(reader.index().unwrap().last().unwrap().offset).unwrap();
+    let point = AppendPoint::find(&archive[..last + 20]).unwrap();
+
+    assert_eq!(point.offset(), last as u64);
+    assert_eq!(point.dropped_bytes(), 20);
+    assert_eq!(point.block_count(), 6);
+    assert_eq!(point.game_count(), 48);
+
+    let path = temp_archive("unfinished", &archive[..last + 20]);
+    append(&path, |_| {}, true);
+    let appended = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    check_archive(&appended, 98);
+    let mut expected = games(&archive)[..48].to_vec();
+    expected.extend(games(&archive));
+    assert_eq!(games(&appended), expected);
+}
+
+#[test]
+fn uncommitted_appends_are_rolled_back() {
+    let archive = sample_archive(|_| {});
+    let path = temp_archive("rolled-back", &archive);
+
+    append(&path, |_| {}, false);
+    let rolled_back = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(rolled_back, archive);
+}
+
+#[test]
+fn appended_blocks_use_the_archive_dictionary() {
+    let archive = sample_archive(|serializer| {
+        serializer.set_compression_level(Some(3));
+        serializer.set_dictionary_size(Some(1024));
+    });
+    let path = temp_archive("dictionary", &archive);
+
+    append(
+        &path,
+        |serializer| serializer.set_compression_level(Some(3)),
+        true,
+    );
+    let appended = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    check_archive(&appended, 100);
+    let dictionary = ArchiveReader::from_bytes(&archive)
+        .unwrap()
+        .dictionary()
+        .unwrap()
+        .to_vec();
+    assert_eq!(
+        ArchiveReader::from_bytes(&appended).unwrap().dictionary(),
+        Some(dictionary.as_slice())
+    );
+    assert_eq!(games(&appended).len(), 100);
+}
+
+#[test]
+fn legacy_and_damaged_archives_are_refused() {
+    let archive = sample_archive(|_| {});
+    let mut legacy = Vec::new();
+    for block in ArchiveReader::from_bytes(&archive).unwrap().blocks() {
+        let data = block.unwrap().data();
+        legacy.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        legacy.extend_from_slice(data);
+    }
+    assert!(AppendPoint::find(&legacy).is_err());
+
+    // A flipped byte in the last block's data is caught by its checksum.
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    let last = usize::try_from(reader.index().unwrap().last().unwrap().offset).unwrap();
+    let mut damaged = archive.clone();
+    damaged[last + 20] ^= 0xFF;
+    assert!(AppendPoint::find(&damaged).is_err());
+}
@@ -0,0 +1,30 @@
+//! Helpers shared by the integration tests.
+
+// Every test file is its own crate, and not all of them use every helper.
+#![allow(dead_code)]
+
+use std::fs;
+
+use chessb::{
+    converter::Converter, generated_chess::Game, reader::ArchiveReader, serializer::Serializer,
+};
+
+/// Converts `games.pgn` into an archive with blocks of up to 8 games, with the serializer set up by `setup`.
+pub fn sample_archive(setup: impl FnOnce(&mut Serializer<Vec<u8>>)) -> Vec<u8> {
+    let pgn = fs::read("games.pgn").unwrap();
+    let mut serializer = Serializer::new(Vec::new());
+    serializer.set_max_games_per_block(8);
+    setup(&mut serializer);
+    let mut converter = Converter::new(pgn.as_slice(), serializer);
+    while converter.next_game().unwrap() {}
+    converter.finish().unwrap().0
+}
+
+/// Decodes every game in an archive.
+pub fn games(archive: &[u8]) -> Vec<Game> {
+    ArchiveReader::from_bytes(archive)
+        .unwrap()
+        .games_owned()
+        .map(Result::unwrap)
+        .collect()
+}
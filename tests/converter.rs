@@ -0,0 +1,434 @@
+use std::io::{self, Write};
+
+use chessb::{
+    converter::{ConversionError, ConversionErrorKind, Converter, ErrorPolicy},
+    generated_chess::{CheckKind, Game, GameResult},
+    serializer::Serializer,
+};
+
+mod common;
+
+use common::games;
+
+/// Converts the given PGN and decodes every game in the resulting archive.
+fn convert(pgn: &str) -> Vec<Game> {
+    convert_skipping(pgn).0
+}
+
+/// Converts the given PGN with `ErrorPolicy::Skip`, returning the decoded games and the skipped ones.
+fn convert_skipping(pgn: &str) -> (Vec<Game>, Vec<ConversionError>) {
+    convert_with(pgn, false)
+}
+
+/// Like `convert_skipping`, but replays every game while converting.
+fn convert_validating(pgn: &str) -> (Vec<Game>, Vec<ConversionError>) {
+    convert_with(pgn, true)
+}
+
+fn convert_with(pgn: &str, validate: bool) -> (Vec<Game>, Vec<ConversionError>) {
+    let mut converter = Converter::new(pgn.as_bytes(), Serializer::new(Vec::new()));
+    converter.set_error_policy(ErrorPolicy::Skip);
+    converter.set_validation(validate);
+    while converter.next_game().unwrap() {}
+    let (output, stats) = converter.finish().unwrap();
+    (games(&output), stats.skipped_games)
+}
+
+#[test]
+fn game_without_result_token_uses_result_tag() {
+    let games = convert(
+        r#"[Result "0-1"]
+
+1. f3 e5 2. g4 Qh4#
+
+[Result "1-0"]
+
+1. e4 e5 1-0
+"#,
+    );
+
+    assert_eq!(games.len(), 2);
+    assert_eq!(games[0].result, GameResult::BlackWin);
+    assert_eq!(games[0].moves.len(), 4);
+    assert_eq!(games[1].result, GameResult::WhiteWin);
+    assert_eq!(games[1].moves.len(), 2);
+}
+
+#[test]
+fn game_without_any_result_is_unknown() {
+    let games = convert(
+        r#"[Event "No result"]
+
+1. d4 d5 2. c4
+
+[Event "Next"]
+
+1. e4 1/2-1/2
+"#,
+    );
+
+    assert_eq!(games.len(), 2);
+    assert_eq!(games[0].result, GameResult::Unknown);
+    assert_eq!(games[0].moves.len(), 3);
+    assert_eq!(games[1].result, GameResult::Draw);
+    assert_eq!(games[1].moves.len(), 1);
+}
+
+#[test]
+fn result_token_overrides_result_tag() {
+    let games = convert(
+        r#"[Result "*"]
+
+1. e4 e5 0-1
+"#,
+    );
+
+    assert_eq!(games.len(), 1);
+    assert_eq!(games[0].result, GameResult::BlackWin);
+}
+
+#[test]
+fn truncated_final_game_is_kept() {
+    let games = convert(
+        r#"[Event "Complete"]
+
+1. e4 e5 2. Nf3 Nc6 1-0
+
+[Event "Truncated"]
+[Result "0-1"]
+
+1. d4 d5 2. c4 e6 3. Nc"#,
+    );
+
+    assert_eq!(games.len(), 2);
+    assert_eq!(games[0].moves.len(), 4);
+    assert_eq!(games[0].result, GameResult::WhiteWin);
+    assert_eq!(games[1].moves.len(), 4);
+    assert_eq!(games[1].result, GameResult::BlackWin);
+}
+
+#[test]
+fn truncated_headers_keep_earlier_games() {
+    let pgn = r#"1. e4 e5 1-0
+
+[Event "Cut off"]
+[White "#;
+
+    let mut output = Vec::new();
+    {
+        let mut converter = Converter::new(pgn.as_bytes(), Serializer::new(&mut output));
+        assert!(converter.next_game().unwrap());
+        assert!(converter.next_game().is_err());
+    }
+
+    let games = games(&output);
+    assert_eq!(games.len(), 1);
+    assert_eq!(games[0].moves.len(), 2);
+    assert_eq!(games[0].result, GameResult::WhiteWin);
+}
+
+#[test]
+fn skipped_game_does_not_leak_moves() {
+    let games = convert(
+        r#"[FEN "not a position"]
+
+1. e4 e5 2. Nf3 *
+
+[Event "Next"]
+
+1. d4 1-0
+"#,
+    );
+
+    assert_eq!(games.len(), 1);
+    assert_eq!(games[0].moves.len(), 1);
+    assert_eq!(games[0].result, GameResult::WhiteWin);
+}
+
+#[test]
+fn unsupported_moves_abort_by_default() {
+    let pgn = r#"[Event "Fine"]
+
+1. e4 e5 1-0
+
+[Event "Null move"]
+
+1. e4 -- 2. d4 *
+"#;
+
+    let mut output = Vec::new();
+    let mut converter = Converter::new(pgn.as_bytes(), Serializer::new(&mut output));
+    assert!(converter.next_game().unwrap());
+
+    let error = converter.next_game().unwrap_err();
+    let error = error.downcast_ref::<ConversionError>().unwrap();
+    assert_eq!(error.kind, ConversionErrorKind::NullMove);
+    assert_eq!(error.game_index, 1);
+    assert_eq!(error.byte_offset, pgn.find("[Event \"Null").unwrap() as u64);
+}
+
+#[test]
+fn skip_policy_records_unsupported_games() {
+    let pgn = r#"1. e4 -- 1-0
+
+[Event "Drop"]
+
+1. e4 d5 2. N@f3 *
+
+1. d4 d5 1/2-1/2
+"#;
+
+    let (games, skipped) = convert_skipping(pgn);
+
+    assert_eq!(games.len(), 1);
+    assert_eq!(games[0].moves.len(), 2);
+    assert_eq!(games[0].result, GameResult::Draw);
+
+    assert_eq!(skipped.len(), 2);
+    assert_eq!(skipped[0].kind, ConversionErrorKind::NullMove);
+    assert_eq!(skipped[0].game_index, 0);
+    assert_eq!(skipped[0].byte_offset, 0);
+    assert_eq!(skipped[1].kind, ConversionErrorKind::PieceDrop);
+    assert_eq!(skipped[1].game_index, 1);
+    assert_eq!(skipped[1].byte_offset, pgn.find("[Event").unwrap() as u64);
+}
+
+#[test]
+fn stats_account_for_every_game() {
+    let pgn = r#"1. e4 e5 1-0
+
+1. e4 -- *
+
+1. e4 e5 2. Nf3 0-1
+"#;
+
+    let mut converter = Converter::new(pgn.as_bytes(), Serializer::new(Vec::new()));
+    converter.set_error_policy(ErrorPolicy::Skip);
+    while converter.next_game().unwrap() {}
+    let (output, stats) = converter.finish().unwrap();
+
+    assert_eq!(stats.games_read, 3);
+    assert_eq!(stats.games_written, 2);
+    assert_eq!(stats.skipped_games.len(), 1);
+    assert_eq!(stats.skipped_by_reason().get("null_move"), Some(&1));
+    assert_eq!(stats.blocks.len(), 1);
+    assert_eq!(stats.blocks[0].games, 2);
+    assert_eq!(stats.bytes_written, output.len() as u64);
+}
+
+#[test]
+fn check_and_checkmate_suffixes_are_kept() {
+    let games = convert("1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Bxf7+ Ke7 5. Qxe5# 1-0\n");
+
+    assert_eq!(games.len(), 1);
+    let checks: Vec<_> = games[0].moves.iter().map(|m| m.check).collect();
+    assert_eq!(checks[6], Some(CheckKind::Check));
+    assert_eq!(checks[8], Some(CheckKind::Checkmate));
+    assert_eq!(checks.iter().flatten().count(), 2);
+}
+
+#[test]
+fn game_info_is_filled_from_tags() {
+    let games = convert(
+        r#"[Event "Rated Blitz game"]
+[Site "https://lichess.org/abcd1234"]
+[White "alice"]
+[Black "bob"]
+[WhiteElo "1850"]
+[BlackElo "1792"]
+
+1. e4 e5 1-0
+"#,
+    );
+
+    let info = games[0].info.as_deref().unwrap();
+    assert_eq!(info.event.as_deref(), Some("Rated Blitz game"));
+    assert_eq!(info.site.as_deref(), Some("https://lichess.org/abcd1234"));
+    assert_eq!(info.url.as_deref(), Some("https://lichess.org/abcd1234"));
+    assert_eq!(info.white_player.as_deref(), Some("alice"));
+    assert_eq!(info.black_player.as_deref(), Some("bob"));
+    assert_eq!((info.white_elo, info.black_elo), (1850, 1792));
+}
+
+#[test]
+fn unknown_and_missing_tags_are_left_out() {
+    let games = convert(
+        r#"[Event "?"]
+[Site "Berlin GER"]
+[White ""]
+[WhiteElo "?"]
+[BlackElo "-"]
+
+1. e4 e5 1-0
+
+[Site "http://example.com/game/1"]
+
+1. d4 d5 *
+
+[Result "1/2-1/2"]
+
+1. c4 c5 *
+"#,
+    );
+
+    // Site isn't a link, so there's no URL. Unknown ratings are stored as 0.
+    let info = games[0].info.as_deref().unwrap();
+    assert_eq!(info.event, None);
+    assert_eq!(info.site.as_deref(), Some("Berlin GER"));
+    assert_eq!(info.url, None);
+    assert_eq!(info.white_player, None);
+    assert_eq!(info.black_player, None);
+    assert_eq!((info.white_elo, info.black_elo), (0, 0));
+
+    let info = games[1].info.as_deref().unwrap();
+    assert_eq!(info.url.as_deref(), Some("http://example.com/game/1"));
+
+    // None of the tags we keep, so no info table at all.
+    assert!(games[2].info.is_none());
+}
+
+#[test]
+fn variations_are_not_stored_in_the_mainline() {
+    // The null move in the second variation would get the game skipped if variations were read.
+    let games = convert("1. e4 (1. d4 d5 2. c4) e5 (1... --) 2. Nf3 *\n");
+
+    assert_eq!(games.len(), 1);
+    assert_eq!(games[0].moves.len(), 3);
+
+    // Replaying the game doesn't play the variations either, so their moves aren't checked against the mainline.
+    let (games, skipped) =
+        convert_validating("1. e4 (1. d4 d5 2. c4) 1... e5 (1... c5 2. Nf3) 2. Nf3 *\n");
+    assert!(skipped.is_empty());
+    assert_eq!(games.len(), 1);
+    assert_eq!(games[0].moves.len(), 3);
+}
+
+#[test]
+fn validation_rejects_illegal_moves() {
+    let pgn = r#"1. e4 e5 2. Ke3 1-0
+
+1. e4 e5 2. Nf3 Nc6 *
+
+1. d4 d5 2. Nd2 Nd7 3. Nf3 *
+"#;
+
+    let (games, skipped) = convert_validating(pgn);
+
+    assert_eq!(games.len(), 1);
+    assert_eq!(games[0].moves.len(), 4);
+    assert_eq!(skipped.len(), 2);
+    assert_eq!(
+        skipped[0].kind,
+        ConversionErrorKind::IllegalMove {
+            ply: 3,
+            san: "Ke3".to_owned()
+        }
+    );
+    // Both knights can reach f3, so the move is ambiguous.
+    assert_eq!(skipped[1].game_index, 2);
+    assert!(matches!(
+        skipped[1].kind,
+        ConversionErrorKind::IllegalMove { ply: 5, .. }
+    ));
+
+    // Without validation, the same games are stored as-is.
+    let (games, skipped) = convert_skipping(pgn);
+    assert_eq!(games.len(), 3);
+    assert!(skipped.is_empty());
+}
+
+#[test]
+fn validation_checks_results_of_finished_games() {
+    let pgn = r#"1. f3 e5 2. g4 Qh4# 1-0
+
+1. f3 e5 2. g4 Qh4# 0-1
+
+[FEN "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"]
+
+1... Kh8 *
+
+[FEN "7k/8/6K1/8/8/8/8/5Q2 w - - 0 1"]
+
+1. Qf7 1/2-1/2
+
+1. e4 e5 1-0
+
+1. f3 e5 2. g4 Qh4# *
+"#;
+
+    let (games, skipped) = convert_validating(pgn);
+
+    // An unknown result fits any final position, checkmate included.
+    assert_eq!(games.len(), 4);
+    assert_eq!(skipped.len(), 2);
+    assert_eq!(skipped[0].game_index, 0);
+    assert_eq!(
+        skipped[0].kind,
+        ConversionErrorKind::ResultMismatch {
+            stored: GameResult::WhiteWin,
+            expected: GameResult::BlackWin
+        }
+    );
+    assert_eq!(skipped[1].game_index, 2);
+    assert!(matches!(
+        skipped[1].kind,
+        ConversionErrorKind::IllegalMove { ply: 1, .. }
+    ));
+}
+
+/// A writer that fails once more than `capacity` bytes have been written, like a full disk.
+#[derive(Debug)]
+struct FullDisk {
+    written: Vec<u8>,
+    capacity: usize,
+}
+
+impl Write for FullDisk {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written.len() + buf.len() > self.capacity {
+            return Err(io::Error::new(io::ErrorKind::StorageFull, "disk full"));
+        }
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn finish_reports_write_errors() {
+    let pgn = "1. e4 e5 1-0\n\n1. d4 d5 0-1\n";
+    let full_disk = || FullDisk {
+        written: vec![],
+        capacity: 64,
+    };
+
+    let mut converter = Converter::new(pgn.as_bytes(), Serializer::new(full_disk()));
+    while converter.next_game().unwrap() {}
+    let error = converter.finish().unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<io::Error>().unwrap().kind(),
+        io::ErrorKind::StorageFull
+    );
+
+    // Dropping an unfinished converter tries to finish the archive, but doesn't panic when that fails.
+    let mut converter = Converter::new(pgn.as_bytes(), Serializer::new(full_disk()));
+    while converter.next_game().unwrap() {}
+    drop(converter);
+}
+
+#[test]
+fn flush_ends_the_block_but_not_the_archive() {
+    let pgn = "1. e4 e5 1-0\n\n1. d4 d5 0-1\n\n1. c4 c5 1/2-1/2\n";
+
+    let mut converter = Converter::new(pgn.as_bytes(), Serializer::new(Vec::new()));
+    assert!(converter.next_game().unwrap());
+    converter.flush().unwrap();
+    while converter.next_game().unwrap() {}
+    let (output, stats) = converter.finish().unwrap();
+
+    assert_eq!(stats.blocks.len(), 2);
+    assert_eq!(games(&output), convert(pgn));
+}
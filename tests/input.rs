@@ -0,0 +1,124 @@
+use std::{
+    fs,
+    io::{Cursor, Read, Write},
+};
+
+use chessb::{
+    converter::Converter,
+    generated_chess::Game,
+    input::{Compression, decompress},
+    reader::ArchiveReader,
+    serializer::Serializer,
+};
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+fn decompressed(data: Vec<u8>) -> Vec<u8> {
+    let mut output = Vec::new();
+    decompress(Cursor::new(data))
+        .unwrap()
+        .read_to_end(&mut output)
+        .unwrap();
+    output
+}
+
+fn games(pgn: impl Read) -> Vec<Game> {
+    let mut converter = Converter::new(pgn, Serializer::new(Vec::new()));
+    while converter.next_game().unwrap() {}
+    let archive = converter.finish().unwrap().0;
+    ArchiveReader::from_bytes(&archive)
+        .unwrap()
+        .games_owned()
+        .map(Result::unwrap)
+        .collect()
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, data) in files {
+        writer.start_file(*name, options).unwrap();
+        writer.write_all(data).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+#[test]
+fn compression_is_detected_from_magic_bytes() {
+    let pgn = fs::read("games.pgn").unwrap();
+    assert_eq!(Compression::detect(&pgn), Compression::None);
+    assert_eq!(Compression::detect(&gzip(&pgn)), Compression::Gzip);
+    assert_eq!(
+        Compression::detect(&zstd::encode_all(pgn.as_slice(), 3).unwrap()),
+        Compression::Zstd
+    );
+    assert_eq!(Compression::detect(b"BZh91AY&SY"), Compression::Bzip2);
+    assert_eq!(
+        Compression::detect(&[0xFD, b'7', b'z', b'X', b'Z', 0x00, 0x00]),
+        Compression::Xz
+    );
+    assert_eq!(
+        Compression::detect(&zip(&[("games.pgn", &pgn)])),
+        Compression::Zip
+    );
+    // Too short to be anything but PGN.
+    assert_eq!(Compression::detect(b"1."), Compression::None);
+}
+
+#[test]
+fn every_format_decompresses_to_the_original() {
+    let pgn = fs::read("games.pgn").unwrap();
+
+    let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    bzip2.write_all(&pgn).unwrap();
+    let mut xz = liblzma::write::XzEncoder::new(Vec::new(), 6);
+    xz.write_all(&pgn).unwrap();
+
+    let compressed = [
+        pgn.clone(),
+        zstd::encode_all(pgn.as_slice(), 3).unwrap(),
+        gzip(&pgn),
+        bzip2.finish().unwrap(),
+        xz.finish().unwrap(),
+        zip(&[("games.pgn", &pgn)]),
+    ];
+    for data in compressed {
+        assert_eq!(decompressed(data), pgn);
+    }
+}
+
+#[test]
+fn concatenated_gzip_members_are_all_read() {
+    let pgn = fs::read("games.pgn").unwrap();
+    let (first, second) = pgn.split_at(pgn.len() / 2);
+    let mut data = gzip(first);
+    data.extend(gzip(second));
+
+    assert_eq!(decompressed(data), pgn);
+}
+
+#[test]
+fn every_pgn_file_in_a_zip_archive_is_read() {
+    let pgn = fs::read_to_string("games.pgn").unwrap();
+    let (first, second) = pgn.split_at(pgn.find("\n[Event").unwrap());
+    let archive = zip(&[
+        ("README.txt", b"Not a chess game".as_slice()),
+        ("part1.pgn", first.trim_end().as_bytes()),
+        ("more/part2.PGN", second.as_bytes()),
+    ]);
+
+    let converted = games(decompress(Cursor::new(archive)).unwrap());
+    assert_eq!(converted.len(), 50);
+    assert_eq!(converted, games(pgn.as_bytes()));
+}
+
+#[test]
+fn tiny_inputs_are_passed_through() {
+    assert_eq!(decompressed(Vec::new()), b"");
+    assert_eq!(decompressed(b"*".to_vec()), b"*");
+}
@@ -0,0 +1,141 @@
+use std::fs;
+
+use chessb::{converter::Converter, merge::merge, reader::ArchiveReader, serializer::Serializer};
+
+mod common;
+
+use common::{games, sample_archive};
+
+fn block_game_counts(archive: &[u8]) -> Vec<u32> {
+    ArchiveReader::from_bytes(archive)
+        .unwrap()
+        .index()
+        .unwrap()
+        .iter()
+        .map(|entry| entry.game_count)
+        .collect()
+}
+
+#[test]
+fn merged_games_keep_their_order() {
+    let first = sample_archive(|serializer| serializer.set_max_games_per_block(8));
+    let second = sample_archive(|serializer| serializer.set_max_games_per_block(20));
+    let archives = [
+        ArchiveReader::from_bytes(&first).unwrap(),
+        ArchiveReader::from_bytes(&second).unwrap(),
+    ];
+
+    let mut serializer = Serializer::new(Vec::new());
+    serializer.set_max_games_per_block(8);
+    let report = merge(&archives, &mut serializer).unwrap();
+    let merged = serializer.into_inner().unwrap();
+
+    let reader = ArchiveReader::from_bytes(&merged).unwrap();
+    assert!(reader.verify().is_ok());
+    assert_eq!(reader.game_count(), Some(100));
+    assert_eq!(report.games, 100);
+    let mut expected = games(&first);
+    expected.extend(games(&second));
+    assert_eq!(games(&merged), expected);
+}
+
+#[test]
+fn partly_filled_blocks_are_repacked() {
+    // Six blocks of 8 games and one of 2, twice over.
+    let archive = sample_archive(|serializer| serializer.set_max_games_per_block(8));
+    let archives = [
+        ArchiveReader::from_bytes(&archive).unwrap(),
+        ArchiveReader::from_bytes(&archive).unwrap(),
+    ];
+
+    let mut serializer = Serializer::new(Vec::new());
+    serializer.set_max_games_per_block(8);
+    let report = merge(&archives, &mut serializer).unwrap();
+    let merged = serializer.into_inner().unwrap();
+
+    // The first archive's full blocks are copied. Its last 2 games make every block of the second archive start
+    // part way into a block, so all of those are re-encoded.
+    assert_eq!(report.copied_blocks, 6);
+    assert_eq!(report.repacked_games, 52);
+    assert_eq!(
+        block_game_counts(&merged),
+        [8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8, 4]
+    );
+}
+
+#[test]
+fn re_encoded_games_are_deduplicated_again() {
+    let archive = sample_archive(|serializer| serializer.set_max_games_per_block(7));
+    let pgn = fs::read("games.pgn").unwrap();
+    let mut converter = Converter::new(pgn.as_slice(), Serializer::new(Vec::new()));
+    while converter.next_game().unwrap() {}
+    let (_, converted) = converter.finish().unwrap();
+
+    let mut serializer = Serializer::new(Vec::new());
+    for block in ArchiveReader::from_bytes(&archive).unwrap().blocks() {
+        let block = block.unwrap().decode().unwrap();
+        for game in block.games().unwrap() {
+            serializer.add_game_ref(&game.unwrap()).unwrap();
+        }
+    }
+    serializer.finish().unwrap();
+
+    // One block of all 50 games, with as many distinct moves as converting them into one block gives.
+    assert_eq!(serializer.block_stats().len(), 1);
+    assert_eq!(
+        serializer.block_stats()[0].unique_moves,
+        converted.blocks[0].unique_moves
+    );
+    assert_eq!(games(&serializer.into_inner().unwrap()), games(&archive));
+}
+
+#[test]
+fn archives_with_their_own_dictionaries_can_be_merged() {
+    let compressed = |block_games| {
+        sample_archive(|serializer| {
+            serializer.set_max_games_per_block(block_games);
+            serializer.set_compression_level(Some(3));
+            serializer.set_dictionary_size(Some(1024));
+        })
+    };
+    let (first, second) = (compressed(5), compressed(9));
+    let archives = [
+        ArchiveReader::from_bytes(&first).unwrap(),
+        ArchiveReader::from_bytes(&second).unwrap(),
+    ];
+
+    let mut serializer = Serializer::new(Vec::new());
+    serializer.set_max_games_per_block(5);
+    serializer.set_compression_level(Some(3));
+    serializer.set_dictionary_size(Some(1024));
+    let report = merge(&archives, &mut serializer).unwrap();
+    let merged = serializer.into_inner().unwrap();
+
+    let reader = ArchiveReader::from_bytes(&merged).unwrap();
+    assert!(reader.verify().is_ok());
+    assert!(reader.dictionary().is_some());
+    // Every block of the first archive, and the last block of the second, which has 5 games left after its other
+    // blocks are split up.
+    assert_eq!(report.copied_blocks, 11);
+    let mut expected = games(&first);
+    expected.extend(games(&second));
+    assert_eq!(games(&merged), expected);
+}
+
+#[test]
+fn damaged_archives_are_refused() {
+    let mut archive = sample_archive(|serializer| serializer.set_max_games_per_block(8));
+    let second_block = usize::try_from(
+        ArchiveReader::from_bytes(&archive)
+            .unwrap()
+            .index()
+            .unwrap()[1]
+            .offset,
+    )
+    .unwrap();
+    archive[second_block + 20] ^= 0xFF;
+
+    let archives = [ArchiveReader::from_bytes(&archive).unwrap()];
+    let mut serializer = Serializer::new(Vec::new());
+    assert!(merge(&archives, &mut serializer).is_err());
+}
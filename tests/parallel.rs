@@ -0,0 +1,144 @@
+use std::fs;
+
+use chessb::{
+    converter::{ConversionError, ConversionErrorKind, Converter, ErrorPolicy},
+    parallel::ParallelConverter,
+    reader::ArchiveReader,
+    serializer::Serializer,
+};
+
+mod common;
+
+use common::games;
+
+/// Converts on a single thread, returning the archive and the skipped games.
+fn convert(pgn: &[u8]) -> (Vec<u8>, Vec<ConversionError>) {
+    let mut converter = Converter::new(pgn, Serializer::new(Vec::new()));
+    converter.set_error_policy(ErrorPolicy::Skip);
+    while converter.next_game().unwrap() {}
+    let (output, stats) = converter.finish().unwrap();
+    (output, stats.skipped_games)
+}
+
+/// Converts on the given number of threads, in chunks of about 2 KB.
+fn convert_parallel(pgn: &[u8], threads: usize) -> (Vec<u8>, Vec<ConversionError>) {
+    let mut converter = ParallelConverter::new(pgn, Serializer::new(Vec::new()));
+    converter.set_error_policy(ErrorPolicy::Skip);
+    converter.set_chunk_size(2048);
+    converter.set_threads(threads);
+    let (output, stats) = converter.convert().unwrap();
+    (output, stats.skipped_games)
+}
+
+#[test]
+fn parallel_conversion_matches_sequential_conversion() {
+    let pgn = fs::read("games.pgn").unwrap();
+    let (sequential, _) = convert(&pgn);
+    let (parallel, skipped) = convert_parallel(&pgn, 4);
+
+    assert!(skipped.is_empty());
+    assert_eq!(games(&parallel), games(&sequential));
+
+    let reader = ArchiveReader::from_bytes(&parallel).unwrap();
+    assert!(reader.block_count().unwrap() > 1);
+    assert!(reader.verify().is_ok());
+
+    // The output doesn't depend on the number of threads.
+    assert_eq!(convert_parallel(&pgn, 1).0, parallel);
+    assert_eq!(convert_parallel(&pgn, 3).0, parallel);
+}
+
+#[test]
+fn skipped_games_are_numbered_across_chunks() {
+    let mut pgn = fs::read_to_string("games.pgn").unwrap();
+    pgn.push_str("\n[Event \"Null move\"]\n\n1. e4 -- 2. d4 *\n");
+    pgn.push_str("\n[Event \"Fine\"]\n\n1. e4 e5 1-0\n");
+    pgn.push_str("\n[Event \"Drop\"]\n\n1. e4 d5 2. N@f3 *\n");
+
+    let (sequential, expected) = convert(pgn.as_bytes());
+    let (parallel, skipped) = convert_parallel(pgn.as_bytes(), 4);
+
+    assert_eq!(skipped.len(), 2);
+    assert_eq!(skipped, expected);
+    assert_eq!(skipped[0].game_index, 50);
+    assert_eq!(skipped[1].kind, ConversionErrorKind::PieceDrop);
+    assert_eq!(
+        skipped[1].byte_offset,
+        pgn.find("[Event \"Drop").unwrap() as u64
+    );
+    assert_eq!(games(&parallel), games(&sequential));
+}
+
+#[test]
+fn abort_keeps_the_chunks_before_the_error() {
+    let mut pgn = fs::read_to_string("games.pgn").unwrap();
+    pgn.push_str("\n[Event \"Null move\"]\n\n1. e4 -- 2. d4 *\n");
+
+    let mut output = Vec::new();
+    let mut converter = ParallelConverter::new(pgn.as_bytes(), Serializer::new(&mut output));
+    converter.set_chunk_size(2048);
+    let error = converter.convert().unwrap_err();
+    let error = error.downcast_ref::<ConversionError>().unwrap();
+    assert_eq!(error.kind, ConversionErrorKind::NullMove);
+    assert_eq!(error.game_index, 50);
+
+    // The archive is finished, with every chunk before the one holding the bad game.
+    let reader = ArchiveReader::from_bytes(&output).unwrap();
+    let count = reader.game_count().unwrap() as usize;
+    assert!(count > 0 && count < 50);
+    let expected = games(&convert(pgn.as_bytes()).0);
+    assert_eq!(games(&output), expected[..count]);
+}
+
+#[test]
+fn games_without_tags_are_converted_sequentially() {
+    let pgn = "1. e4 e5 1-0\n\n1. d4 d5 0-1\n\n1. c4 c5 1/2-1/2\n\n".repeat(100);
+
+    let mut converter = ParallelConverter::new(pgn.as_bytes(), Serializer::new(Vec::new()));
+    converter.set_chunk_size(64);
+    let (output, stats) = converter.convert().unwrap();
+
+    assert_eq!(stats.games_written, 300);
+    assert_eq!(stats.blocks.len(), 1);
+    assert_eq!(stats.bytes_written, output.len() as u64);
+    assert_eq!(games(&output), games(&convert(pgn.as_bytes()).0));
+}
+
+#[test]
+fn input_that_cant_be_split_is_converted_as_it_is_read() {
+    // Chunks are cut from the tagged games, then the games without tags are converted sequentially, since no chunk
+    // can be split off of them.
+    let mut pgn = fs::read_to_string("games.pgn").unwrap();
+    pgn.push_str(&"\n1. e4 e5 1-0\n\n1. d4 d5 0-1\n".repeat(1000));
+    pgn.push_str("\n1. e4 -- 2. d4 *\n\n1. c4 c5 1/2-1/2\n");
+
+    let (sequential, expected) = convert(pgn.as_bytes());
+    let (parallel, skipped) = convert_parallel(pgn.as_bytes(), 4);
+
+    assert_eq!(skipped, expected);
+    assert_eq!(skipped[0].game_index, 2050);
+    assert_eq!(skipped[0].byte_offset, pgn.find("1. e4 --").unwrap() as u64);
+    assert_eq!(games(&parallel), games(&sequential));
+    assert!(
+        ArchiveReader::from_bytes(&parallel)
+            .unwrap()
+            .verify()
+            .is_ok()
+    );
+}
+
+#[test]
+fn games_are_not_split_inside_comments() {
+    let pgn = "[Event \"Brace {\"]\n\n1. e4 {A game quoted in a comment:\n\n[Event \"Quoted\"]\n\n1. d4 d5} e5 1-0\n\n\
+               [Event \"Line\"]\n\n1. d4 ; not a comment { here\n\n[Event \"Next\"]\n\n1. c4 c5 0-1\n\n"
+        .repeat(20);
+
+    let mut converter = ParallelConverter::new(pgn.as_bytes(), Serializer::new(Vec::new()));
+    converter.set_chunk_size(16);
+    converter.set_threads(4);
+    let (output, stats) = converter.convert().unwrap();
+
+    assert_eq!(stats.games_written, 60);
+    assert!(stats.blocks.len() > 1);
+    assert_eq!(games(&output), games(&convert(pgn.as_bytes()).0));
+}
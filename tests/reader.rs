@@ -0,0 +1,531 @@
+use std::fs;
+
+use chessb::{
+    converter::Converter,
+    generated_chess::{BlockIndex, BlockIndexEntry, Game},
+    header::{
+        ENCODING_NONE, ENCODING_ZSTD, ENCODING_ZSTD_DICTIONARY, FOOTER_LEN, FileHeader, HEADER_LEN,
+        MAGIC,
+    },
+    reader::{ArchiveReader, BlockFormat, BlockIterator, ReadError},
+    serializer::Serializer,
+};
+use rayon::iter::ParallelIterator;
+
+/// Converts `games.pgn` into an archive with several small blocks.
+fn sample_archive() -> Vec<u8> {
+    archive_with_compression(None)
+}
+
+/// Like `sample_archive`, but compresses the blocks at the given zstd level.
+fn archive_with_compression(level: Option<i32>) -> Vec<u8> {
+    archive_with_dictionary(level, None)
+}
+
+/// Like `archive_with_compression`, but also trains a dictionary of up to the given size on the first block.
+fn archive_with_dictionary(level: Option<i32>, dictionary_size: Option<usize>) -> Vec<u8> {
+    let pgn = fs::read("games.pgn").unwrap();
+    let mut serializer = Serializer::new(Vec::new());
+    serializer.set_max_games_per_block(8);
+    serializer.set_compression_level(level);
+    serializer.set_dictionary_size(dictionary_size);
+    let mut converter = Converter::new(pgn.as_slice(), serializer);
+    while converter.next_game().unwrap() {}
+    converter.finish().unwrap().0
+}
+
+/// Rewrites an archive the way it was stored before file headers existed: no header, no checksums and no index.
+fn legacy_archive(archive: &[u8]) -> Vec<u8> {
+    let mut legacy = Vec::new();
+    for block in ArchiveReader::from_bytes(archive).unwrap().blocks() {
+        let data = block.unwrap().data();
+        legacy.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        legacy.extend_from_slice(data);
+    }
+    legacy
+}
+
+#[test]
+fn reads_blocks_and_games() {
+    let archive = sample_archive();
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+
+    let blocks: Vec<_> = reader.blocks().map(Result::unwrap).collect();
+    assert_eq!(blocks.len(), 7);
+    assert_eq!(blocks[0].offset(), HEADER_LEN);
+    assert_eq!(blocks[1].offset(), HEADER_LEN + 12 + blocks[0].data().len());
+    assert_eq!(blocks[0].stored_len(), 12 + blocks[0].data().len());
+
+    let counts: Vec<_> = blocks.iter().map(|b| b.game_count().unwrap()).collect();
+    assert_eq!(counts, [8, 8, 8, 8, 8, 8, 2]);
+
+    assert_eq!(reader.games().map(Result::unwrap).count(), 50);
+}
+
+#[test]
+fn parallel_games_match_sequential_games() {
+    let archive = sample_archive();
+    let reader = ArchiveReader::from_reader(archive.as_slice()).unwrap();
+
+    let mut sequential: Vec<_> = reader
+        .games()
+        .map(|game| game.unwrap().moves().unwrap().len())
+        .collect();
+    let mut parallel: Vec<_> = reader
+        .par_games()
+        .map(|game| game.unwrap().moves().unwrap().len())
+        .collect();
+
+    sequential.sort_unstable();
+    parallel.sort_unstable();
+    assert_eq!(sequential, parallel);
+}
+
+#[test]
+fn truncated_archive_reports_an_error() {
+    let archive = sample_archive();
+    let first_block_end = ArchiveReader::from_bytes(&archive)
+        .unwrap()
+        .blocks()
+        .next()
+        .unwrap()
+        .unwrap()
+        .stored_len()
+        + HEADER_LEN;
+
+    // Cut off inside the second block's data.
+    let truncated = &archive[..first_block_end + 100];
+    let reader = ArchiveReader::from_bytes(truncated).unwrap();
+    let results: Vec<_> = reader.blocks().collect();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].is_ok());
+    assert!(matches!(
+        results[1],
+        Err(ReadError::TruncatedBlock { offset, available: 88, .. }) if offset == first_block_end
+    ));
+
+    // Cut off inside the second block's length prefix.
+    let truncated = &archive[..first_block_end + 2];
+    let reader = ArchiveReader::from_bytes(truncated).unwrap();
+    let games: Vec<_> = reader.games().collect();
+    assert_eq!(games.len(), 9);
+    assert!(matches!(
+        games.last(),
+        Some(Err(ReadError::TruncatedLength { offset })) if *offset == first_block_end
+    ));
+}
+
+#[test]
+fn corrupt_block_reports_an_error() {
+    let mut archive = sample_archive();
+    // Point the root table offset of the first block past the end of the block.
+    archive[HEADER_LEN + 12..HEADER_LEN + 16].copy_from_slice(&u32::MAX.to_le_bytes());
+
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    let first = reader.games().next().unwrap();
+    assert!(matches!(
+        first,
+        Err(ReadError::InvalidBlock {
+            offset: HEADER_LEN,
+            ..
+        })
+    ));
+
+    // The other blocks are still readable.
+    let errors = reader.par_games().filter(Result::is_err).count();
+    assert_eq!(errors, 1);
+}
+
+#[test]
+fn archives_start_with_a_file_header() {
+    let archive = sample_archive();
+    assert_eq!(archive[..4], MAGIC);
+
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    assert_eq!(reader.header(), Some(FileHeader::CURRENT));
+    assert_eq!(reader.blocks_offset(), HEADER_LEN);
+
+    // Even an archive without games has a header.
+    let (empty, _) = Converter::new(&b""[..], Serializer::new(Vec::new()))
+        .finish()
+        .unwrap();
+    assert_eq!(empty[..HEADER_LEN], FileHeader::CURRENT.to_bytes());
+    let reader = ArchiveReader::from_bytes(&empty).unwrap();
+    assert_eq!(reader.block_count(), Some(0));
+    assert_eq!(reader.game_count(), Some(0));
+    assert_eq!(reader.games().count(), 0);
+}
+
+#[test]
+fn archives_without_a_header_are_still_readable() {
+    let legacy = legacy_archive(&sample_archive());
+
+    let reader = ArchiveReader::from_bytes(&legacy).unwrap();
+    assert_eq!(reader.header(), None);
+    assert!(!reader.has_checksums());
+    assert_eq!(reader.blocks_offset(), 0);
+    assert_eq!(reader.blocks().next().unwrap().unwrap().offset(), 0);
+    assert_eq!(reader.games().map(Result::unwrap).count(), 50);
+}
+
+#[test]
+fn unsupported_headers_are_rejected() {
+    let mut archive = sample_archive();
+
+    archive[4..6].copy_from_slice(&2u16.to_le_bytes());
+    archive[6..8].copy_from_slice(&3u16.to_le_bytes());
+    let error = ArchiveReader::from_bytes(&archive).err().unwrap();
+    assert!(matches!(
+        error,
+        ReadError::UnsupportedVersion { major: 2, minor: 3 }
+    ));
+    assert_eq!(
+        error.to_string(),
+        "archive uses format version 2.3, but only version 1.x is supported"
+    );
+
+    // Newer minor versions are fine.
+    archive[4..6].copy_from_slice(&1u16.to_le_bytes());
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    assert_eq!(reader.header().unwrap().minor, 3);
+
+    archive[8..12].copy_from_slice(&0x11u32.to_le_bytes());
+    assert!(matches!(
+        ArchiveReader::from_bytes(&archive),
+        Err(ReadError::UnsupportedFlags { flags: 0x10 })
+    ));
+
+    assert!(matches!(
+        ArchiveReader::from_bytes(&archive[..6]),
+        Err(ReadError::TruncatedHeader)
+    ));
+}
+
+#[test]
+fn finished_archives_have_a_block_index() {
+    let archive = sample_archive();
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+
+    let scanned: Vec<_> = reader
+        .blocks()
+        .map(Result::unwrap)
+        .scan(0, |first_game, block| {
+            let game_count = block.game_count().unwrap() as u32;
+            let entry = BlockIndexEntry {
+                offset: block.offset() as u64,
+                first_game: *first_game,
+                game_count,
+            };
+            *first_game += u64::from(game_count);
+            Some(entry)
+        })
+        .collect();
+    assert_eq!(reader.index(), Some(scanned.as_slice()));
+    assert_eq!(reader.block_count(), Some(7));
+    assert_eq!(reader.game_count(), Some(50));
+
+    let last = reader.block(6).unwrap().unwrap();
+    assert_eq!(last.offset(), scanned[6].offset as usize);
+    assert_eq!(last.game_count().unwrap(), 2);
+    assert!(reader.block(7).is_none());
+    assert_eq!(reader.par_games().filter(Result::is_ok).count(), 50);
+}
+
+#[test]
+fn archives_without_an_index_are_scanned() {
+    let pgn = fs::read("games.pgn").unwrap();
+    let mut output = Vec::new();
+    let mut serializer = Serializer::new(&mut output);
+    serializer.set_max_games_per_block(8);
+    let mut converter = Converter::new(pgn.as_slice(), serializer);
+    while converter.next_game().unwrap() {}
+    // Simulate an interrupted conversion: the full blocks are written, but the archive was never finished.
+    std::mem::forget(converter);
+
+    let reader = ArchiveReader::from_bytes(&output).unwrap();
+    assert_eq!(reader.index(), None);
+    assert_eq!(reader.game_count(), None);
+    assert_eq!(reader.blocks().count(), 6);
+    assert_eq!(reader.block(5).unwrap().unwrap().game_count().unwrap(), 8);
+    assert!(reader.block(6).is_none());
+    assert_eq!(reader.par_games().filter(Result::is_ok).count(), 48);
+}
+
+/// Replaces the block index of an archive with one listing the given entries.
+fn with_index(archive: &[u8], entries: Vec<BlockIndexEntry>) -> Vec<u8> {
+    let footer = archive.len() - FOOTER_LEN;
+    let index_offset = u64::from_le_bytes(archive[footer..footer + 8].try_into().unwrap());
+    let mut builder = planus::Builder::new();
+    let index = builder.finish(&BlockIndex { blocks: entries }, None);
+
+    let mut rewritten = archive[..index_offset as usize].to_vec();
+    rewritten.extend_from_slice(&(index.len() as u32).to_le_bytes());
+    rewritten.extend_from_slice(index);
+    rewritten.extend_from_slice(&index_offset.to_le_bytes());
+    rewritten.extend_from_slice(&archive[archive.len() - 4..]);
+    rewritten
+}
+
+#[test]
+fn damaged_index_falls_back_to_walking_the_blocks() {
+    let mut archive = sample_archive();
+    let footer = archive.len() - FOOTER_LEN;
+    archive[footer..footer + 8].copy_from_slice(&3u64.to_le_bytes());
+
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    assert_eq!(reader.index(), None);
+    assert_eq!(reader.blocks().count(), 7);
+    assert_eq!(reader.games().filter(Result::is_ok).count(), 50);
+    assert!(reader.game(49).unwrap().is_ok());
+
+    let report = reader.verify();
+    assert_eq!(report.valid_games, 50);
+    assert!(matches!(
+        report.errors[..],
+        [ReadError::InvalidIndex { offset: 3, .. }]
+    ));
+}
+
+#[test]
+fn inconsistent_indexes_are_ignored() {
+    let archive = sample_archive();
+    let entries = ArchiveReader::from_bytes(&archive)
+        .unwrap()
+        .index()
+        .unwrap()
+        .to_vec();
+    // Rebuilding the index as it is gives the same archive back, so only the changes below make it unusable.
+    assert_eq!(with_index(&archive, entries.clone()), archive);
+
+    let mut swapped = entries.clone();
+    swapped.swap(1, 2);
+    let mut miscounted = entries.clone();
+    miscounted[3].first_game += 1;
+    let mut not_from_zero = entries;
+    for entry in &mut not_from_zero {
+        entry.first_game += 8;
+    }
+
+    let expected = ArchiveReader::from_bytes(&archive)
+        .unwrap()
+        .game_owned(10)
+        .unwrap()
+        .unwrap();
+    for entries in [swapped, miscounted, not_from_zero] {
+        let rewritten = with_index(&archive, entries);
+        let reader = ArchiveReader::from_bytes(&rewritten).unwrap();
+        assert_eq!(reader.index(), None);
+        assert_eq!(reader.game_owned(10).unwrap().unwrap(), expected);
+        assert!(matches!(
+            reader.verify().errors[..],
+            [ReadError::InvalidIndex { .. }]
+        ));
+    }
+}
+
+#[test]
+fn games_can_be_looked_up_by_index() {
+    let archive = sample_archive();
+    let indexed = ArchiveReader::from_bytes(&archive).unwrap();
+    let mut scanned = archive.clone();
+    scanned.truncate(scanned.len() - FOOTER_LEN);
+    let scanned = ArchiveReader::from_bytes(&scanned).unwrap();
+    assert!(scanned.index().is_none());
+
+    let expected: Vec<_> = indexed.games_owned().map(Result::unwrap).collect();
+    for reader in [&indexed, &scanned] {
+        for (n, expected) in expected.iter().enumerate() {
+            let game = reader.game(n as u64).unwrap().unwrap();
+            assert_eq!(&Game::try_from(game).unwrap(), expected, "game {n}");
+        }
+        assert!(reader.game(50).is_none());
+        assert!(reader.game(u64::MAX).is_none());
+    }
+}
+
+#[test]
+fn verify_accepts_intact_archives() {
+    let archive = sample_archive();
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    let report = reader.verify();
+    assert!(report.is_ok(), "{:?}", report.errors);
+    assert_eq!(report.valid_blocks, 7);
+    assert_eq!(report.valid_games, 50);
+    let last = reader.block(6).unwrap().unwrap();
+    assert_eq!(report.last_good_offset, last.offset() + last.stored_len());
+
+    let legacy = legacy_archive(&archive);
+    let report = ArchiveReader::from_bytes(&legacy).unwrap().verify();
+    assert!(report.is_ok(), "{:?}", report.errors);
+    assert_eq!(report.valid_games, 50);
+    assert_eq!(report.last_good_offset, legacy.len());
+}
+
+#[test]
+fn verify_reports_corrupted_blocks() {
+    let mut archive = sample_archive();
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    let third = reader.block(2).unwrap().unwrap();
+    let (offset, end) = (third.offset(), third.offset() + third.stored_len());
+    drop(reader);
+
+    // Flip a bit in the middle of the third block.
+    archive[(offset + end) / 2] ^= 1;
+
+    let report = ArchiveReader::from_bytes(&archive).unwrap().verify();
+    assert_eq!(report.valid_blocks, 6);
+    assert_eq!(report.valid_games, 42);
+    assert_eq!(report.errors.len(), 1);
+    assert!(matches!(
+        report.errors[0],
+        ReadError::ChecksumMismatch { offset: o, .. } if o == offset
+    ));
+    assert_eq!(report.last_good_offset, offset);
+}
+
+#[test]
+fn verify_reports_truncation() {
+    let archive = sample_archive();
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    let fifth = reader.block(4).unwrap().unwrap().offset();
+
+    let truncated = &archive[..fifth + 20];
+    let report = ArchiveReader::from_bytes(truncated).unwrap().verify();
+    assert_eq!(report.valid_blocks, 4);
+    assert_eq!(report.valid_games, 32);
+    assert!(matches!(
+        report.errors[..],
+        [ReadError::TruncatedBlock { offset, .. }] if offset == fifth
+    ));
+    assert_eq!(report.last_good_offset, fifth);
+}
+
+#[test]
+fn compressed_blocks_read_like_uncompressed_ones() {
+    let plain = sample_archive();
+    let compressed = archive_with_compression(Some(3));
+    assert!(compressed.len() < plain.len());
+
+    let plain = ArchiveReader::from_bytes(&plain).unwrap();
+    let reader = ArchiveReader::from_bytes(&compressed).unwrap();
+    let blocks: Vec<_> = reader.blocks().map(Result::unwrap).collect();
+    assert!(blocks.iter().all(|block| block.encoding() == ENCODING_ZSTD));
+    for (block, expected) in blocks.iter().zip(plain.blocks()) {
+        let expected = expected.unwrap();
+        assert_eq!(expected.encoding(), ENCODING_NONE);
+        assert_eq!(block.decode().unwrap().data(), expected.data());
+    }
+
+    let expected: Vec<_> = plain.games_owned().map(Result::unwrap).collect();
+    let games: Vec<_> = reader.games_owned().map(Result::unwrap).collect();
+    assert_eq!(games, expected);
+    assert_eq!(reader.game_owned(42).unwrap().unwrap(), expected[42]);
+    assert_eq!(reader.par_games_owned().filter(Result::is_ok).count(), 50);
+
+    // Compressed games can't be borrowed from the archive.
+    assert!(matches!(
+        reader.game(42),
+        Some(Err(ReadError::CompressedBlock { offset })) if offset == blocks[5].offset()
+    ));
+    assert!(matches!(
+        reader.games().next(),
+        Some(Err(ReadError::CompressedBlock { offset: HEADER_LEN }))
+    ));
+    assert_eq!(reader.par_games().filter(Result::is_err).count(), 7);
+
+    let report = reader.verify();
+    assert!(report.is_ok(), "{:?}", report.errors);
+    assert_eq!(report.valid_games, 50);
+}
+
+#[test]
+fn unknown_block_encodings_are_rejected() {
+    let mut archive = archive_with_compression(Some(3));
+    archive[HEADER_LEN + 8..HEADER_LEN + 12].copy_from_slice(&7u32.to_le_bytes());
+
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    assert!(matches!(
+        reader.blocks().next(),
+        Some(Err(ReadError::UnsupportedEncoding {
+            offset: HEADER_LEN,
+            encoding: 7
+        }))
+    ));
+
+    // A block that claims to be compressed but isn't can't be decoded.
+    let mut plain = sample_archive();
+    plain[HEADER_LEN + 8..HEADER_LEN + 12].copy_from_slice(&ENCODING_ZSTD.to_le_bytes());
+    let reader = ArchiveReader::from_bytes(&plain).unwrap();
+    assert!(matches!(
+        reader.games_owned().next(),
+        Some(Err(ReadError::Decompression {
+            offset: HEADER_LEN,
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn blocks_can_share_a_trained_dictionary() {
+    let compressed = archive_with_compression(Some(3));
+    let with_dictionary = archive_with_dictionary(Some(3), Some(1024));
+
+    let plain = ArchiveReader::from_bytes(&compressed).unwrap();
+    let reader = ArchiveReader::from_bytes(&with_dictionary).unwrap();
+    assert!(reader.header().unwrap().has_dictionary());
+    let dictionary = reader.dictionary().unwrap();
+    assert_eq!(dictionary.len(), 1024);
+    assert_eq!(reader.blocks_offset(), HEADER_LEN + 4 + dictionary.len());
+
+    // The dictionary is stored once, and every block gets smaller for it.
+    let blocks: Vec<_> = reader.blocks().map(Result::unwrap).collect();
+    assert!(
+        blocks
+            .iter()
+            .all(|block| block.encoding() == ENCODING_ZSTD_DICTIONARY)
+    );
+    let block_bytes = |reader: &ArchiveReader| -> usize {
+        reader
+            .blocks()
+            .map(|block| block.unwrap().stored_len())
+            .sum()
+    };
+    assert!(block_bytes(&reader) < block_bytes(&plain));
+
+    let expected: Vec<_> = plain.games_owned().map(Result::unwrap).collect();
+    let games: Vec<_> = reader.games_owned().map(Result::unwrap).collect();
+    assert_eq!(games, expected);
+    assert_eq!(reader.game_owned(17).unwrap().unwrap(), expected[17]);
+    assert_eq!(reader.par_games_owned().filter(Result::is_ok).count(), 50);
+    assert!(reader.verify().is_ok());
+
+    // Without compression, there's nothing to use a dictionary for.
+    let uncompressed = archive_with_dictionary(None, Some(1024));
+    assert_eq!(uncompressed, sample_archive());
+}
+
+#[test]
+fn dictionary_blocks_need_the_dictionary() {
+    let archive = archive_with_dictionary(Some(3), Some(1024));
+    let reader = ArchiveReader::from_bytes(&archive).unwrap();
+    let format = BlockFormat {
+        dictionary: None,
+        ..reader.block_format()
+    };
+
+    let mut blocks = BlockIterator::starting_at(&archive, reader.blocks_offset(), format);
+    assert!(matches!(
+        blocks.next(),
+        Some(Err(ReadError::UnsupportedEncoding {
+            encoding: ENCODING_ZSTD_DICTIONARY,
+            ..
+        }))
+    ));
+
+    // A dictionary that's cut off makes the header unreadable.
+    let archive = archive_with_dictionary(Some(3), Some(1024));
+    assert!(matches!(
+        ArchiveReader::from_bytes(&archive[..HEADER_LEN + 100]),
+        Err(ReadError::TruncatedHeader)
+    ));
+}
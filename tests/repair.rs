@@ -0,0 +1,193 @@
+use chessb::{
+    reader::{ArchiveReader, ReadError},
+    repair::repair,
+    serializer::Serializer,
+};
+
+mod common;
+
+use common::{games, sample_archive};
+
+/// Gets the offset and stored length of every block.
+fn block_spans(archive: &[u8]) -> Vec<(usize, usize)> {
+    ArchiveReader::from_bytes(archive)
+        .unwrap()
+        .blocks()
+        .map(|block| {
+            let block = block.unwrap();
+            (block.offset(), block.stored_len())
+        })
+        .collect()
+}
+
+fn repaired(damaged: &[u8]) -> (Vec<u8>, chessb::repair::RepairReport) {
+    let mut output = Vec::new();
+    let report = repair(damaged, &mut Serializer::new(&mut output)).unwrap();
+
+    let reader = ArchiveReader::from_bytes(&output).unwrap();
+    assert!(reader.verify().is_ok());
+    assert_eq!(reader.game_count(), Some(report.kept_games));
+    (output, report)
+}
+
+#[test]
+fn intact_archives_are_copied_unchanged() {
+    let archive = sample_archive(|_| {});
+    let (output, report) = repaired(&archive);
+
+    assert!(report.damaged.is_empty());
+    assert_eq!(report.kept_blocks, 7);
+    assert_eq!(report.kept_games, 50);
+    assert_eq!(output, archive);
+}
+
+#[test]
+fn truncated_final_block_is_dropped() {
+    let archive = sample_archive(|_| {});
+    let spans = block_spans(&archive);
+    let (last_offset, _) = spans[6];
+    let truncated = &archive[..last_offset + 30];
+
+    let (output, report) = repaired(truncated);
+
+    assert_eq!(report.kept_blocks, 6);
+    assert_eq!(report.kept_games, 48);
+    assert_eq!(report.damaged.len(), 1);
+    assert_eq!(report.damaged[0].offset, last_offset);
+    assert_eq!(report.bytes_lost(), 30);
+    assert!(matches!(
+        report.damaged[0].error,
+        ReadError::TruncatedBlock { .. }
+    ));
+    assert_eq!(games(&output), games(&archive)[..48]);
+}
+
+#[test]
+fn corrupted_blocks_are_skipped() {
+    let mut archive = sample_archive(|_| {});
+    let spans = block_spans(&archive);
+    let original = games(&archive);
+
+    // Corrupt the data of the second block, and zero out the length prefix of the fifth, so the walk has to
+    // find its way to the sixth block on its own.
+    let (second, second_len) = spans[1];
+    archive[second + second_len / 2] ^= 0xFF;
+    let (fifth, fifth_len) = spans[4];
+    archive[fifth..fifth + 4].fill(0);
+
+    let (output, report) = repaired(&archive);
+
+    assert_eq!(report.kept_blocks, 5);
+    assert_eq!(report.kept_games, 34);
+    assert_eq!(report.damaged.len(), 2);
+    assert_eq!(report.damaged[0].offset, second);
+    assert_eq!(report.damaged[0].length, second_len);
+    assert!(matches!(
+        report.damaged[0].error,
+        ReadError::ChecksumMismatch { .. }
+    ));
+    assert_eq!(report.damaged[1].offset, fifth);
+    assert_eq!(report.damaged[1].length, fifth_len);
+
+    let expected: Vec<_> = original[..8]
+        .iter()
+        .chain(&original[16..32])
+        .chain(&original[40..])
+        .cloned()
+        .collect();
+    assert_eq!(games(&output), expected);
+}
+
+#[test]
+fn legacy_archives_are_upgraded() {
+    let archive = sample_archive(|_| {});
+    let mut legacy = Vec::new();
+    for block in ArchiveReader::from_bytes(&archive).unwrap().blocks() {
+        let data = block.unwrap().data();
+        legacy.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        legacy.extend_from_slice(data);
+    }
+
+    let (output, report) = repaired(&legacy);
+
+    assert!(report.damaged.is_empty());
+    assert_eq!(output, archive);
+}
+
+#[test]
+fn repair_can_compress_blocks() {
+    let archive = sample_archive(|_| {});
+    let mut output = Vec::new();
+    let mut serializer = Serializer::new(&mut output);
+    serializer.set_compression_level(Some(3));
+    let report = repair(&archive, &mut serializer).unwrap();
+    drop(serializer);
+
+    assert!(report.damaged.is_empty());
+    assert!(output.len() < archive.len());
+    assert!(ArchiveReader::from_bytes(&output).unwrap().verify().is_ok());
+    assert_eq!(games(&output), games(&archive));
+
+    // Repairing the compressed archive without compression gives back the original.
+    let (uncompressed, _) = repaired(&output);
+    assert_eq!(uncompressed, archive);
+}
+
+#[test]
+fn archives_with_a_dictionary_can_be_repaired() {
+    let archive = sample_archive(|_| {});
+    let mut compressed = Vec::new();
+    {
+        let mut serializer = Serializer::new(&mut compressed);
+        serializer.set_compression_level(Some(3));
+        serializer.set_dictionary_size(Some(1024));
+        repair(&archive, &mut serializer).unwrap();
+    }
+    assert!(
+        ArchiveReader::from_bytes(&compressed)
+            .unwrap()
+            .dictionary()
+            .is_some()
+    );
+
+    // Cut off the last block, which also drops the index.
+    let spans = block_spans(&compressed);
+    let (output, report) = repaired(&compressed[..spans[6].0 + 10]);
+    assert_eq!(report.kept_blocks, 6);
+    assert_eq!(games(&output), games(&archive)[..48]);
+}
+
+#[test]
+fn blocks_after_a_bad_length_are_found_again() {
+    let archive = sample_archive(|_| {});
+    let spans = block_spans(&archive);
+    let original = games(&archive);
+
+    // The fifth block's length still reads, but doesn't lead to the sixth block. With an index, the walk picks up
+    // at the next indexed block, and without one it searches for it.
+    let (fifth, fifth_len) = spans[4];
+    let mut damaged = archive.clone();
+    damaged[fifth..fifth + 4].copy_from_slice(&16u32.to_le_bytes());
+    let unindexed = &damaged[..spans[6].0 + spans[6].1];
+    assert!(
+        ArchiveReader::from_bytes(unindexed)
+            .unwrap()
+            .index()
+            .is_none()
+    );
+
+    for damaged in [&damaged[..], unindexed] {
+        let (output, report) = repaired(damaged);
+
+        assert_eq!(report.kept_blocks, 6);
+        assert_eq!(report.damaged.len(), 1);
+        assert_eq!(report.damaged[0].offset, fifth);
+        assert_eq!(report.damaged[0].length, fifth_len);
+        let expected: Vec<_> = original[..32]
+            .iter()
+            .chain(&original[40..])
+            .cloned()
+            .collect();
+        assert_eq!(games(&output), expected);
+    }
+}
@@ -0,0 +1,245 @@
+//! Round-trip conformance tests: every PGN file in `tests/corpus` (plus the benchmark's `games.pgn`) is converted
+//! to a chess binary, decoded again, and exported back to PGN. At each step the games are compared against what a
+//! plain PGN parse of the original file says they should be.
+
+use std::{fs, ops::ControlFlow, path::Path};
+
+use chessb::{
+    converter::{Converter, ErrorPolicy},
+    generated_chess::GameResult,
+    pgn_writer::PgnWriter,
+    reader::ArchiveReader,
+    serializer::Serializer,
+    utils::{move_ref_to_san, outcome_to_game_result, start_position},
+};
+use pgn_reader::{Reader, SanPlus, Skip, Visitor};
+use shakmaty::{EnPassantMode, Position, fen::Fen};
+
+/// A game as far as the archive is concerned: where it starts, its mainline, and its result.
+#[derive(Debug, PartialEq, Eq)]
+struct ExpectedGame {
+    /// Normalized FEN of the start position, if the game doesn't start from the standard position.
+    start_position: Option<String>,
+    moves: Vec<SanPlus>,
+    result: GameResult,
+}
+
+/// Collects the mainline of every game, independently of the converter.
+struct ReferenceVisitor;
+
+impl Visitor for ReferenceVisitor {
+    type Tags = ExpectedGame;
+    type Movetext = ExpectedGame;
+    type Output = ExpectedGame;
+
+    fn begin_tags(&mut self) -> ControlFlow<Self::Output, Self::Tags> {
+        ControlFlow::Continue(ExpectedGame {
+            start_position: None,
+            moves: vec![],
+            result: GameResult::Unknown,
+        })
+    }
+
+    fn tag(
+        &mut self,
+        tags: &mut Self::Tags,
+        name: &[u8],
+        value: pgn_reader::RawTag<'_>,
+    ) -> ControlFlow<Self::Output> {
+        match name {
+            b"FEN" => tags.start_position = Some(normalize_fen(&value.decode_utf8_lossy())),
+            b"Result" => {
+                if let Ok(outcome) = shakmaty::Outcome::from_ascii(value.as_bytes()) {
+                    tags.result = outcome_to_game_result(outcome);
+                }
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn begin_movetext(&mut self, tags: Self::Tags) -> ControlFlow<Self::Output, Self::Movetext> {
+        ControlFlow::Continue(tags)
+    }
+
+    fn san(
+        &mut self,
+        movetext: &mut Self::Movetext,
+        san_plus: SanPlus,
+    ) -> ControlFlow<Self::Output> {
+        movetext.moves.push(san_plus);
+        ControlFlow::Continue(())
+    }
+
+    fn begin_variation(
+        &mut self,
+        _movetext: &mut Self::Movetext,
+    ) -> ControlFlow<Self::Output, Skip> {
+        ControlFlow::Continue(Skip(true))
+    }
+
+    fn outcome(
+        &mut self,
+        movetext: &mut Self::Movetext,
+        outcome: shakmaty::Outcome,
+    ) -> ControlFlow<Self::Output> {
+        movetext.result = outcome_to_game_result(outcome);
+        ControlFlow::Continue(())
+    }
+
+    fn end_game(&mut self, movetext: Self::Movetext) -> Self::Output {
+        movetext
+    }
+}
+
+fn normalize_fen(fen: &str) -> String {
+    let position = start_position(Some(fen)).unwrap();
+    Fen::from_position(&position, EnPassantMode::Legal).to_string()
+}
+
+fn parse_reference(pgn: &[u8]) -> Vec<ExpectedGame> {
+    let mut reader = Reader::new(pgn);
+    reader
+        .read_games(&mut ReferenceVisitor)
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn convert(pgn: &[u8]) -> Vec<u8> {
+    let mut converter = Converter::new(pgn, Serializer::new(Vec::new()));
+    converter.set_error_policy(ErrorPolicy::Abort);
+    converter.set_validation(true);
+    while converter.next_game().unwrap() {}
+    converter.finish().unwrap().0
+}
+
+/// Decodes every game in an archive, replaying each one with shakmaty to make sure the stored moves are legal.
+fn decode(archive: &[u8]) -> Vec<ExpectedGame> {
+    let mut games = vec![];
+    for block in ArchiveReader::from_bytes(archive).unwrap().blocks() {
+        let block = block.unwrap().decode().unwrap();
+        for game in block.games().unwrap() {
+            let game = game.unwrap();
+            let fen = game.start_position().unwrap();
+            let mut position = start_position(fen).unwrap();
+
+            let mut moves = vec![];
+            for move_ref in game.moves().unwrap() {
+                let san_plus = move_ref_to_san(&move_ref.unwrap()).unwrap();
+                let mv = san_plus.san.to_move(&position).unwrap_or_else(|err| {
+                    panic!("stored move {san_plus} is not legal in {position:?}: {err}")
+                });
+                position.play_unchecked(mv);
+                moves.push(san_plus);
+            }
+
+            games.push(ExpectedGame {
+                start_position: fen.map(str::to_owned),
+                moves,
+                result: game.result().unwrap(),
+            });
+        }
+    }
+    games
+}
+
+fn export(archive: &[u8]) -> Vec<u8> {
+    let mut writer = PgnWriter::new(Vec::new());
+    for block in ArchiveReader::from_bytes(archive).unwrap().blocks() {
+        let block = block.unwrap().decode().unwrap();
+        writer.write_block(block.data()).unwrap();
+    }
+    writer.into_inner().unwrap()
+}
+
+fn assert_round_trips(path: &Path) {
+    let pgn = fs::read(path).unwrap();
+    let expected = parse_reference(&pgn);
+    assert!(!expected.is_empty(), "{} has no games", path.display());
+
+    let archive = convert(&pgn);
+    let decoded = decode(&archive);
+    assert_eq!(
+        decoded.len(),
+        expected.len(),
+        "game count of {}",
+        path.display()
+    );
+    for (index, (decoded, expected)) in decoded.iter().zip(&expected).enumerate() {
+        assert_eq!(decoded, expected, "game {index} of {}", path.display());
+    }
+
+    // Exporting to PGN and parsing that again has to give back the same games...
+    let exported = export(&archive);
+    let reparsed = parse_reference(&exported);
+    assert_eq!(reparsed, expected, "exported PGN of {}", path.display());
+
+    // ...and converting the exported PGN has to give back the same archive.
+    assert_eq!(convert(&exported), archive, "archive of {}", path.display());
+}
+
+#[test]
+fn corpus_round_trips() {
+    let mut paths: Vec<_> = fs::read_dir("tests/corpus")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pgn"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    for path in paths {
+        assert_round_trips(&path);
+    }
+}
+
+#[test]
+fn sample_games_round_trip() {
+    assert_round_trips(Path::new("games.pgn"));
+}
+
+#[test]
+fn small_blocks_round_trip() {
+    let pgn = fs::read("games.pgn").unwrap();
+
+    let mut output = Vec::new();
+    {
+        let mut serializer = Serializer::new(&mut output);
+        serializer.set_max_games_per_block(7);
+        let mut converter = Converter::new(pgn.as_slice(), serializer);
+        while converter.next_game().unwrap() {}
+    }
+
+    let block_count = ArchiveReader::from_bytes(&output).unwrap().blocks().count();
+    assert_eq!(block_count, parse_reference(&pgn).len().div_ceil(7));
+    assert_eq!(decode(&output), parse_reference(&pgn));
+}
+
+#[test]
+fn byte_limited_blocks_round_trip() {
+    let pgn = fs::read("games.pgn").unwrap();
+
+    let mut output = Vec::new();
+    {
+        let mut serializer = Serializer::new(&mut output);
+        serializer.set_max_games_per_block(usize::MAX);
+        serializer.set_max_block_bytes(4096);
+        let mut converter = Converter::new(pgn.as_slice(), serializer);
+        while converter.next_game().unwrap() {}
+    }
+
+    let reader = ArchiveReader::from_bytes(&output).unwrap();
+    let sizes: Vec<_> = reader
+        .blocks()
+        .map(|block| block.unwrap().data().len())
+        .collect();
+    assert!(sizes.len() > 1);
+    // Every block but the last is finished by the game that takes it past the limit.
+    let (last, full) = sizes.split_last().unwrap();
+    assert!(
+        full.iter().all(|&size| (4096..8192).contains(&size)),
+        "{sizes:?}"
+    );
+    assert!(*last < 8192);
+    assert_eq!(decode(&output), parse_reference(&pgn));
+}
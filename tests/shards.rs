@@ -0,0 +1,149 @@
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
+
+use chessb::{
+    converter::Converter,
+    parallel::ParallelConverter,
+    reader::ArchiveReader,
+    serializer::{Serializer, ShardLimit},
+};
+
+mod common;
+
+use common::games;
+
+/// Archives finished by a sharded serializer, in order.
+type Finished = Arc<Mutex<Vec<Vec<u8>>>>;
+
+/// A serializer that shards into in-memory archives, collecting every finished archive but the last.
+fn sharded_serializer(limit: ShardLimit) -> (Serializer<Vec<u8>>, Finished) {
+    let finished = Arc::new(Mutex::new(Vec::new()));
+    let mut serializer = Serializer::new(Vec::new());
+    let shards = Arc::clone(&finished);
+    serializer.set_shards(limit, move |previous: &mut Vec<u8>| {
+        shards.lock().unwrap().push(std::mem::take(previous));
+        Ok(Vec::new())
+    });
+    (serializer, finished)
+}
+
+/// Converts `games.pgn` with the given serializer, returning every archive it wrote.
+fn convert_sharded(
+    serializer: Serializer<Vec<u8>>,
+    finished: &Mutex<Vec<Vec<u8>>>,
+) -> Vec<Vec<u8>> {
+    let pgn = fs::read("games.pgn").unwrap();
+    let mut converter = Converter::new(pgn.as_slice(), serializer);
+    while converter.next_game().unwrap() {}
+    let (last, stats) = converter.finish().unwrap();
+
+    let mut archives = std::mem::take(&mut *finished.lock().unwrap());
+    archives.push(last);
+    let total: usize = archives.iter().map(Vec::len).sum();
+    assert_eq!(stats.bytes_written, total as u64);
+    archives
+}
+
+/// Checks every archive on its own, and that together they hold every game of `games.pgn` in order.
+fn check_shards(archives: &[Vec<u8>]) {
+    let mut all_games = Vec::new();
+    for archive in archives {
+        let reader = ArchiveReader::from_bytes(archive).unwrap();
+        assert!(reader.verify().is_ok());
+        assert!(reader.index().is_some());
+        all_games.extend(games(archive));
+    }
+
+    let pgn = fs::read("games.pgn").unwrap();
+    let mut converter = Converter::new(pgn.as_slice(), Serializer::new(Vec::new()));
+    while converter.next_game().unwrap() {}
+    assert_eq!(all_games, games(&converter.finish().unwrap().0));
+}
+
+fn game_counts(archives: &[Vec<u8>]) -> Vec<u64> {
+    archives
+        .iter()
+        .map(|archive| {
+            ArchiveReader::from_bytes(archive)
+                .unwrap()
+                .game_count()
+                .unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn shards_by_games_end_right_at_the_limit() {
+    let (mut serializer, finished) = sharded_serializer(ShardLimit::Games(16));
+    serializer.set_max_games_per_block(10);
+    let archives = convert_sharded(serializer, &finished);
+
+    check_shards(&archives);
+    assert_eq!(game_counts(&archives), [16, 16, 16, 2]);
+    // The second block of every shard is cut short to end the shard.
+    let blocks: Vec<_> = ArchiveReader::from_bytes(&archives[0])
+        .unwrap()
+        .index()
+        .unwrap()
+        .iter()
+        .map(|entry| entry.game_count)
+        .collect();
+    assert_eq!(blocks, [10, 6]);
+}
+
+#[test]
+fn no_empty_shard_is_left_at_the_end() {
+    let (serializer, finished) = sharded_serializer(ShardLimit::Games(25));
+    let archives = convert_sharded(serializer, &finished);
+
+    check_shards(&archives);
+    assert_eq!(game_counts(&archives), [25, 25]);
+}
+
+#[test]
+fn shards_by_bytes_end_with_the_block_that_crosses_the_limit() {
+    let (mut serializer, finished) = sharded_serializer(ShardLimit::Bytes(5000));
+    serializer.set_max_games_per_block(5);
+    let archives = convert_sharded(serializer, &finished);
+
+    check_shards(&archives);
+    assert!(archives.len() > 1);
+    for archive in &archives[..archives.len() - 1] {
+        let reader = ArchiveReader::from_bytes(archive).unwrap();
+        let last_block = reader.index().unwrap().iter().last().unwrap().offset as usize;
+        assert!(last_block < 5000 && archive.len() >= 5000);
+    }
+}
+
+#[test]
+fn compressed_shards_get_their_own_dictionary() {
+    let (mut serializer, finished) = sharded_serializer(ShardLimit::Games(25));
+    serializer.set_compression_level(Some(3));
+    serializer.set_dictionary_size(Some(1024));
+    serializer.set_max_games_per_block(5);
+    let archives = convert_sharded(serializer, &finished);
+
+    check_shards(&archives);
+    for archive in &archives {
+        let reader = ArchiveReader::from_bytes(archive).unwrap();
+        assert!(reader.dictionary().is_some());
+    }
+}
+
+#[test]
+fn parallel_conversion_can_be_sharded() {
+    let (serializer, finished) = sharded_serializer(ShardLimit::Games(20));
+    let pgn = fs::read("games.pgn").unwrap();
+    let mut converter = ParallelConverter::new(pgn.as_slice(), serializer);
+    converter.set_chunk_size(2048);
+    let (last, _) = converter.convert().unwrap();
+
+    let mut archives = std::mem::take(&mut *finished.lock().unwrap());
+    archives.push(last);
+    check_shards(&archives);
+    // Blocks are built before they're handed to the serializer, so shards end at the first block past the limit.
+    let counts = game_counts(&archives);
+    assert!(counts[..counts.len() - 1].iter().all(|&count| count >= 20));
+}